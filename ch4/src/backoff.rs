@@ -0,0 +1,92 @@
+use std::{hint, thread, time::Duration};
+
+/// What a waiter does between two failed attempts at taking a lock.
+///
+/// A fresh value is made (through `Default`) for every acquisition, so a
+/// strategy can keep track of how long the current waiter has been at it.
+pub trait Backoff: Default {
+    /// Returns how many times it spun, for the lock stats. Yielding or
+    /// parking counts as zero.
+    fn snooze(&mut self) -> u32;
+}
+
+pub type DefaultBackoff = Spin;
+
+/// Spins once per failed attempt, what `SpinLock` has always done.
+#[derive(Default)]
+pub struct Spin;
+
+impl Backoff for Spin {
+    fn snooze(&mut self) -> u32 {
+        hint::spin_loop();
+        1
+    }
+}
+
+// after this many doublings we stop growing the number of spins.
+const SPIN_LIMIT: u32 = 6;
+
+/// Doubles the number of spins after every failed attempt, up to `2^SPIN_LIMIT`.
+#[derive(Default)]
+pub struct Exponential {
+    step: u32,
+}
+
+impl Backoff for Exponential {
+    fn snooze(&mut self) -> u32 {
+        let spins = 1 << self.step;
+        for _ in 0..spins {
+            hint::spin_loop();
+        }
+        if self.step < SPIN_LIMIT {
+            self.step += 1;
+        }
+        spins
+    }
+}
+
+/// Backs off exponentially for a while, then gives the core away with
+/// `thread::yield_now` so that the lock holder can get scheduled.
+#[derive(Default)]
+pub struct SpinThenYield {
+    spin: Exponential,
+}
+
+impl Backoff for SpinThenYield {
+    fn snooze(&mut self) -> u32 {
+        if self.spin.step < SPIN_LIMIT {
+            self.spin.snooze()
+        } else {
+            thread::yield_now();
+            0
+        }
+    }
+}
+
+// longest a parked waiter sleeps before checking the lock again.
+const MAX_PARK: Duration = Duration::from_millis(1);
+
+/// Backs off exponentially for a while, then parks.
+///
+/// Nobody keeps track of the waiters to unpark them, so this parks with a
+/// timeout that starts at a microsecond and doubles up to `MAX_PARK`.
+#[derive(Default)]
+pub struct SpinThenPark {
+    spin: Exponential,
+    parks: u32,
+}
+
+impl Backoff for SpinThenPark {
+    fn snooze(&mut self) -> u32 {
+        if self.spin.step < SPIN_LIMIT {
+            self.spin.snooze()
+        } else {
+            let timeout = Duration::from_micros(1 << self.parks).min(MAX_PARK);
+            thread::park_timeout(timeout);
+            if timeout < MAX_PARK {
+                self.parks += 1;
+            }
+            0
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    ops::DerefMut,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        LockResult, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::futex;
+
+/// A guard that can let go of its lock for a while, which is all a `Condvar`
+/// needs from it.
+pub trait Unlock {
+    /// Unlocks, runs `f`, and locks again, even if `f` panics. Fails if
+    /// the lock is poisoned once it's locked again.
+    fn unlocked<R>(&mut self, f: impl FnOnce() -> R) -> LockResult<R>;
+}
+
+/// A condition variable for the guards of this crate's locks.
+pub struct Condvar {
+    // bumped on every notification, waiters sleep on it with a futex.
+    counter: AtomicU32,
+    num_waiters: AtomicUsize,
+}
+
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    /// Skips the syscall if nobody is waiting.
+    pub fn notify_one(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            futex::wake_one(&self.counter);
+        }
+    }
+
+    /// Skips the syscall if nobody is waiting.
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            futex::wake_all(&self.counter);
+        }
+    }
+
+    /// Unlocks the guard, waits for a notification and locks it again.
+    /// Like std's, this can wake up spuriously, and returns an error holding
+    /// the guard if the lock is poisoned.
+    pub fn wait<G: Unlock>(&self, mut guard: G) -> LockResult<G> {
+        // we're still holding the lock here, so a notifier that changed the
+        // data after we checked it can't have looked at num_waiters yet.
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+        let counter_value = self.counter.load(Ordering::Relaxed);
+
+        let result = guard.unlocked(|| futex::wait(&self.counter, counter_value));
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+        match result {
+            Ok(()) => Ok(guard),
+            Err(_) => Err(PoisonError::new(guard)),
+        }
+    }
+
+    /// Waits for as long as `condition` returns true.
+    pub fn wait_while<G, T>(
+        &self,
+        mut guard: G,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<G>
+    where
+        G: Unlock + DerefMut<Target = T>,
+        T: ?Sized,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like `wait`, but gives up after `timeout`.
+    pub fn wait_timeout<G: Unlock>(
+        &self,
+        mut guard: G,
+        timeout: Duration,
+    ) -> LockResult<(G, WaitTimeoutResult)> {
+        let start = Instant::now();
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+        let counter_value = self.counter.load(Ordering::Relaxed);
+
+        let result = guard.unlocked(|| futex::wait_timeout(&self.counter, counter_value, timeout));
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+        let timed_out = WaitTimeoutResult(start.elapsed() >= timeout);
+        match result {
+            Ok(()) => Ok((guard, timed_out)),
+            Err(_) => Err(PoisonError::new((guard, timed_out))),
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::atomic::Ordering,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Condvar;
+    use crate::{mutex::Mutex, SpinLock};
+
+    #[test]
+    fn queue_with_spin_lock() {
+        let queue = SpinLock::new(VecDeque::new());
+        let not_empty = Condvar::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..3 {
+                    queue.lock().unwrap().push_back(i);
+                    not_empty.notify_one();
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
+
+            let mut received = Vec::new();
+            let mut q = queue.lock().unwrap();
+            while received.len() < 3 {
+                q = not_empty.wait_while(q, |q| q.is_empty()).unwrap();
+                received.extend(q.drain(..));
+            }
+            assert_eq!(received, [0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn notify_all_with_mutex() {
+        let ready = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let g = condvar.wait_while(ready.lock(), |ready| !*ready).unwrap();
+                    assert!(*g);
+                });
+            }
+
+            while condvar.num_waiters.load(Ordering::Relaxed) < 4 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            *ready.lock() = true;
+            condvar.notify_all();
+        });
+    }
+
+    #[test]
+    fn wait_reports_poisoning() {
+        let lock = SpinLock::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            let g = lock.lock().unwrap();
+            // can only get the lock once we're waiting.
+            let t = s.spawn(|| {
+                let mut g = lock.lock().unwrap();
+                *g = true;
+                condvar.notify_one();
+                panic!("oh no");
+            });
+
+            let result = condvar.wait_while(g, |done| !*done);
+            assert!(*result.err().unwrap().into_inner());
+            assert!(t.join().is_err());
+        });
+    }
+
+    #[test]
+    fn notify_without_waiters_is_skipped() {
+        let condvar = Condvar::new();
+        condvar.notify_one();
+        condvar.notify_all();
+        assert_eq!(condvar.counter.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+
+        let start = Instant::now();
+        let (_g, result) = condvar
+            .wait_timeout(mutex.lock(), Duration::from_millis(20))
+            .unwrap();
+        assert!(result.timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,311 @@
+//! Lock-order checking, behind the `deadlock-detection` feature and only in
+//! debug builds.
+//!
+//! Every thread keeps a list of the locks it holds. Whenever a thread blocks
+//! on a lock while holding others, an edge from each held lock to the new one
+//! goes into a global graph. If the new lock can already reach one of the held
+//! ones, two threads taking them in opposite orders could deadlock, so we panic
+//! right away instead of waiting for it to actually happen. So does locking a
+//! lock the thread already holds, which would otherwise spin or sleep forever,
+//! unless it's a read of an `RwLock` the thread only holds for reading.
+//!
+//! Guards that are sent to and dropped on another thread confuse it, since
+//! the lock stays on the list of the thread that took it.
+
+#[cfg(all(debug_assertions, feature = "deadlock-detection"))]
+pub use enabled::LockOrder;
+
+#[cfg(not(all(debug_assertions, feature = "deadlock-detection")))]
+pub use disabled::LockOrder;
+
+#[cfg(all(debug_assertions, feature = "deadlock-detection"))]
+mod enabled {
+    use std::{
+        backtrace::Backtrace,
+        cell::RefCell,
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering::Relaxed},
+            Arc, Mutex, PoisonError,
+        },
+    };
+
+    // ids are never reused, unlike addresses.
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+    // GRAPH[a][b] is where b was first locked while a was held.
+    type Graph = BTreeMap<usize, BTreeMap<usize, Arc<Backtrace>>>;
+    static GRAPH: Mutex<Graph> = Mutex::new(BTreeMap::new());
+
+    struct Held {
+        id: usize,
+        shared: bool,
+        locked_at: Arc<Backtrace>,
+    }
+
+    thread_local! {
+        static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub struct LockOrder {
+        // 0 until the lock is first used, so `new` can stay const.
+        id: AtomicUsize,
+    }
+
+    impl LockOrder {
+        pub const fn new() -> Self {
+            Self {
+                id: AtomicUsize::new(0),
+            }
+        }
+
+        fn id(&self) -> usize {
+            let id = self.id.load(Relaxed);
+            if id != 0 {
+                return id;
+            }
+            let new = NEXT_ID.fetch_add(1, Relaxed);
+            match self.id.compare_exchange(0, new, Relaxed, Relaxed) {
+                Ok(_) => new,
+                Err(id) => id,
+            }
+        }
+
+        /// Call before blocking on the lock. Panics if that could deadlock.
+        pub fn before_lock(&self) {
+            self.check(false);
+        }
+
+        /// Like `before_lock`, for shared locks a thread can hold more than once.
+        pub fn before_shared_lock(&self) {
+            self.check(true);
+        }
+
+        fn check(&self, shared: bool) {
+            let id = self.id();
+            HELD.with_borrow(|held| {
+                if let Some(h) = held.iter().find(|h| h.id == id && !(shared && h.shared)) {
+                    panic!(
+                        "deadlock: this thread already holds the lock it's trying to lock\n\n\
+                         first locked at:\n{}\n\nlocked again at:\n{}",
+                        h.locked_at,
+                        Backtrace::force_capture(),
+                    );
+                }
+
+                let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+                let held = held.iter().filter(|h| h.id != id);
+                for h in held.clone() {
+                    if let Some(path) = find_path(&graph, id, h.id) {
+                        // path[0] is us, path[1] was locked while we were held.
+                        let other = &graph[&path[0]][&path[1]];
+                        panic!(
+                            "potential deadlock: locks taken in inconsistent order {path:?}\n\n\
+                             the other order was established at:\n{other}\n\n\
+                             this order is being established at:\n{}",
+                            Backtrace::force_capture(),
+                        );
+                    }
+                }
+                for h in held {
+                    if !graph
+                        .get(&h.id)
+                        .is_some_and(|edges| edges.contains_key(&id))
+                    {
+                        let here = Arc::new(Backtrace::force_capture());
+                        graph.entry(h.id).or_default().insert(id, here);
+                    }
+                }
+            });
+        }
+
+        /// Call once the lock is held.
+        pub fn locked(&self) {
+            self.push(false);
+        }
+
+        /// Like `locked`, for when other shared locks of the same lock can
+        /// still be taken on this thread.
+        pub fn locked_shared(&self) {
+            self.push(true);
+        }
+
+        fn push(&self, shared: bool) {
+            let held = Held {
+                id: self.id(),
+                shared,
+                locked_at: Arc::new(Backtrace::force_capture()),
+            };
+            HELD.with_borrow_mut(|h| h.push(held));
+        }
+
+        /// Call when the lock is released.
+        pub fn unlocked(&self) {
+            let id = self.id();
+            // the thread local might already be gone if a guard is dropped
+            // while the thread is shutting down.
+            let _ = HELD.try_with(|held| {
+                let mut held = held.borrow_mut();
+                if let Some(i) = held.iter().rposition(|h| h.id == id) {
+                    held.remove(i);
+                }
+            });
+        }
+    }
+
+    fn find_path(graph: &Graph, from: usize, to: usize) -> Option<Vec<usize>> {
+        let mut path = vec![from];
+        let mut visited = vec![from];
+        if dfs(graph, to, &mut path, &mut visited) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn dfs(graph: &Graph, to: usize, path: &mut Vec<usize>, visited: &mut Vec<usize>) -> bool {
+        let last = *path.last().unwrap();
+        for &next in graph.get(&last).into_iter().flat_map(|edges| edges.keys()) {
+            if visited.contains(&next) {
+                continue;
+            }
+            visited.push(next);
+            path.push(next);
+            if next == to || dfs(graph, to, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+}
+
+#[cfg(not(all(debug_assertions, feature = "deadlock-detection")))]
+mod disabled {
+    pub struct LockOrder;
+
+    impl LockOrder {
+        pub const fn new() -> Self {
+            Self
+        }
+
+        pub fn before_lock(&self) {}
+
+        pub fn before_shared_lock(&self) {}
+
+        pub fn locked(&self) {}
+
+        pub fn locked_shared(&self) {}
+
+        pub fn unlocked(&self) {}
+    }
+}
+
+#[cfg(all(test, debug_assertions, feature = "deadlock-detection"))]
+mod tests {
+    use crate::{
+        mcs_lock::McsLock, mutex::Mutex, rwlock::RwLock, ticket_lock::TicketLock, SpinLock,
+    };
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn relocking_spin_lock() {
+        let lock = SpinLock::new(());
+        let _a = lock.lock().unwrap();
+        let _b = lock.lock().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn relocking_mutex() {
+        let lock = Mutex::new(());
+        let _a = lock.lock();
+        let _b = lock.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn relocking_ticket_lock() {
+        let lock = TicketLock::new(());
+        let _a = lock.lock();
+        let _b = lock.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn relocking_mcs_lock() {
+        let lock = McsLock::new(());
+        let _a = lock.lock();
+        let _b = lock.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn writing_while_reading() {
+        let lock = RwLock::new(());
+        let _a = lock.read();
+        let _b = lock.write();
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds the lock")]
+    fn reading_while_writing() {
+        let lock = RwLock::new(());
+        let _a = lock.write();
+        let _b = lock.read();
+    }
+
+    #[test]
+    fn nested_reads() {
+        let lock = RwLock::new(());
+        let _u = lock.upgradable_read();
+        let _a = lock.read();
+        let _b = lock.read();
+    }
+
+    #[test]
+    fn upgrading_is_not_relocking() {
+        let lock = RwLock::new(0);
+        let g = lock.upgradable_read();
+        *crate::rwlock::UpgradableReadGuard::upgrade(g) += 1;
+        // and the write guard unlocked it again.
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "inconsistent order")]
+    fn inverted_order() {
+        let a = SpinLock::new(());
+        let b = Mutex::new(());
+        let c = SpinLock::new(());
+
+        {
+            let _a = a.lock().unwrap();
+            let _b = b.lock();
+        }
+        {
+            let _b = b.lock();
+            let _c = c.lock().unwrap();
+        }
+        // c -> a closes the loop a -> b -> c -> a.
+        let _c = c.lock().unwrap();
+        let _a = a.lock().unwrap();
+    }
+
+    #[test]
+    fn consistent_order() {
+        let a = SpinLock::new(());
+        let b = SpinLock::new(());
+
+        for _ in 0..3 {
+            let _a = a.lock().unwrap();
+            let _b = b.lock().unwrap();
+        }
+        // released, so taking them one at a time is fine.
+        drop(b.lock());
+        drop(a.lock());
+        // and try_lock never blocks, so it can't deadlock.
+        let _b = b.lock().unwrap();
+        let _a = a.try_lock().unwrap();
+    }
+}
@@ -1,30 +1,79 @@
+// The locks here are a library in all but name; main only exercises a few of them.
+#![allow(dead_code)]
+
 use std::{
     cell::UnsafeCell,
+    marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LockResult, PoisonError, TryLockError, TryLockResult,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+use backoff::{Backoff, DefaultBackoff};
+use condvar::Unlock;
+use lock_order::LockOrder;
+use stats::{HoldTimer, LockStats};
+
+// whether a concrete type is Sync. when the bound holds, the inherent const
+// shadows the trait's one.
+#[cfg(test)]
+macro_rules! is_sync {
+    ($t:ty) => {{
+        struct Probe<T: ?Sized>(std::marker::PhantomData<T>);
+        trait NotSync {
+            const IS_SYNC: bool = false;
+        }
+        impl<T: ?Sized> NotSync for Probe<T> {}
+        #[allow(dead_code)]
+        impl<T: ?Sized + Sync> Probe<T> {
+            const IS_SYNC: bool = true;
+        }
+        <Probe<$t>>::IS_SYNC
+    }};
+}
+
+mod backoff;
+mod condvar;
+mod futex;
+mod lock_order;
+mod mcs_lock;
+mod mutex;
+mod rwlock;
+mod seqlock;
+mod stats;
+mod ticket_lock;
+
 fn main() {
     let x = SpinLock::new(Vec::new());
 
     thread::scope(|s| {
-        s.spawn(|| x.lock().push(1));
+        s.spawn(|| x.lock().unwrap().push(1));
         s.spawn(|| {
-            let mut g = x.lock();
+            let mut g = x.lock().unwrap();
             g.push(2);
             g.push(2);
         });
     });
-    let g = x.lock();
+    let g = x.lock().unwrap();
     assert!(g.as_slice() == [1, 2, 2] || g.as_slice() == [2, 2, 1]);
 }
 
-pub struct Guard<'a, T> {
-    lock: &'a SpinLock<T>,
+pub struct Guard<'a, T, B = DefaultBackoff> {
+    lock: &'a SpinLock<T, B>,
+    // if we were already panicking when we got the lock, a panic
+    // doesn't mean we left the value half-modified.
+    panicking: bool,
+    timer: HoldTimer,
+    // only Sync if T is, &SpinLock<T> alone would make it Sync for any T: Send.
+    _marker: PhantomData<&'a mut T>,
 }
 
-impl<T> Deref for Guard<'_, T> {
+impl<T, B> Deref for Guard<'_, T, B> {
     type Target = T;
     fn deref(&self) -> &T {
         // Safety: the very existance of this Guard
@@ -33,7 +82,7 @@ impl<T> Deref for Guard<'_, T> {
     }
 }
 
-impl<T> DerefMut for Guard<'_, T> {
+impl<T, B> DerefMut for Guard<'_, T, B> {
     fn deref_mut(&mut self) -> &mut T {
         // Safety: the very existance of this Guard
         // guarantees we've exclusivly locked the lock.
@@ -41,31 +90,460 @@ impl<T> DerefMut for Guard<'_, T> {
     }
 }
 
-impl<T> Drop for Guard<'_, T> {
+impl<T, B> Drop for Guard<'_, T, B> {
     fn drop(&mut self) {
-        self.lock.locked.store(false, Ordering::Release);
+        self.lock.raw.unlock(&self.timer, self.panicking);
     }
 }
 
-struct SpinLock<T> {
+impl<'a, T, B: Backoff> Guard<'a, T, B> {
+    /// Narrows the guard down to a part of the value, e.g.
+    /// `Guard::map(guard, |state| &mut state.field)`. The lock stays held
+    /// until the returned guard is dropped.
+    pub fn map<U>(guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedGuard<'a, U> {
+        // Safety: we hold the lock. if f panics, the guard unlocks (and poisons) as usual.
+        let value: *mut U = f(unsafe { &mut *guard.lock.value.get() });
+        MappedGuard::new(guard, value)
+    }
+
+    /// Like `map`, but gives the guard back if `f` returns `None`.
+    pub fn try_map<U>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedGuard<'a, U>, Self> {
+        // Safety: see `map`.
+        match f(unsafe { &mut *guard.lock.value.get() }) {
+            Some(value) => {
+                let value: *mut U = value;
+                Ok(MappedGuard::new(guard, value))
+            }
+            None => Err(guard),
+        }
+    }
+
+    /// Lets go of the lock while `f` runs and takes it again afterwards,
+    /// even if `f` panics. Returns an error holding `f`'s result if the lock
+    /// is poisoned once it's taken again, e.g. because another holder
+    /// panicked in the meantime.
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> LockResult<R> {
+        struct Relock<'g, 'a, T, B: Backoff>(&'g mut Guard<'a, T, B>);
+
+        impl<T, B: Backoff> Drop for Relock<'_, '_, T, B> {
+            fn drop(&mut self) {
+                self.0.lock.raw_lock();
+                self.0.timer = self.0.lock.raw.stats.start_timer();
+            }
+        }
+
+        // letting go on purpose, so this never poisons.
+        guard.lock.raw.unlock(&guard.timer, true);
+        let relock = Relock(guard);
+        let result = f();
+        drop(relock);
+        if guard.lock.is_poisoned() {
+            Err(PoisonError::new(result))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+impl<T, B: Backoff> Unlock for Guard<'_, T, B> {
+    fn unlocked<R>(&mut self, f: impl FnOnce() -> R) -> LockResult<R> {
+        Guard::unlocked(self, f)
+    }
+}
+
+/// A `Guard` that has been narrowed down to part of the locked value with
+/// `Guard::map`. The type of the lock is gone, only the parts needed to
+/// unlock it are left.
+pub struct MappedGuard<'a, U> {
+    raw: &'a RawSpinLock,
+    panicking: bool,
+    timer: HoldTimer,
+    value: *mut U,
+    _lifetime: PhantomData<&'a mut U>,
+}
+
+impl<'a, U> MappedGuard<'a, U> {
+    fn new<T, B>(guard: Guard<'a, T, B>, value: *mut U) -> Self {
+        let lock = guard.lock;
+        let panicking = guard.panicking;
+        let timer = guard.timer;
+        // unlocking is now our job.
+        mem::forget(guard);
+        Self {
+            raw: &lock.raw,
+            panicking,
+            timer,
+            value,
+            _lifetime: PhantomData,
+        }
+    }
+}
+
+unsafe impl<U> Sync for MappedGuard<'_, U> where U: Sync {}
+
+impl<U> Deref for MappedGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        // Safety: value points into the locked value, and we hold the lock.
+        unsafe { &*self.value }
+    }
+}
+
+impl<U> DerefMut for MappedGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // Safety: value points into the locked value, and we hold the lock.
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<U> Drop for MappedGuard<'_, U> {
+    fn drop(&mut self) {
+        self.raw.unlock(&self.timer, self.panicking);
+    }
+}
+
+// everything but the value, so a MappedGuard can unlock without knowing its type.
+struct RawSpinLock {
     locked: AtomicBool,
+    // set when a guard is dropped during a panic, like std's Mutex does.
+    poisoned: AtomicBool,
+    stats: LockStats,
+    order: LockOrder,
+}
+
+impl RawSpinLock {
+    fn unlock(&self, timer: &HoldTimer, panicking: bool) {
+        self.stats.record_release(timer);
+        self.order.unlocked();
+        if !panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+struct SpinLock<T, B = DefaultBackoff> {
+    raw: RawSpinLock,
     value: UnsafeCell<T>,
+    // the strategy only lives in the type, a fresh one is made for each wait.
+    _backoff: PhantomData<fn() -> B>,
 }
 
-unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+unsafe impl<T, B> Sync for SpinLock<T, B> where T: Send {}
 
 impl<T> SpinLock<T> {
     pub const fn new(value: T) -> Self {
+        Self::with_backoff(value)
+    }
+}
+
+impl<T, B: Backoff> SpinLock<T, B> {
+    /// Like `new`, but waits with the `B` strategy,
+    /// e.g. `SpinLock::<_, SpinThenYield>::with_backoff(value)`.
+    pub const fn with_backoff(value: T) -> Self {
         Self {
-            locked: AtomicBool::new(false),
+            raw: RawSpinLock {
+                locked: AtomicBool::new(false),
+                poisoned: AtomicBool::new(false),
+                stats: LockStats::new(),
+                order: LockOrder::new(),
+            },
             value: UnsafeCell::new(value),
+            _backoff: PhantomData,
+        }
+    }
+
+    /// Returns an error holding the guard if a previous holder panicked,
+    /// just like `std::sync::Mutex::lock`.
+    pub fn lock(&self) -> LockResult<Guard<'_, T, B>> {
+        self.raw_lock();
+        self.guard()
+    }
+
+    fn raw_lock(&self) {
+        self.raw.order.before_lock();
+        let mut backoff = B::default();
+        let (mut waits, mut spins) = (0, 0);
+        while self.raw.locked.swap(true, Ordering::Acquire) {
+            spins += u64::from(backoff.snooze());
+            waits += 1;
+        }
+        self.raw.stats.record_acquire(waits, spins);
+        self.raw.order.locked();
+    }
+
+    /// Takes the lock only if nobody else is holding it right now.
+    pub fn try_lock(&self) -> TryLockResult<Guard<'_, T, B>> {
+        if self.raw.locked.swap(true, Ordering::Acquire) {
+            Err(TryLockError::WouldBlock)
+        } else {
+            self.raw.stats.record_acquire(0, 0);
+            self.raw.order.locked();
+            Ok(self.guard()?)
+        }
+    }
+
+    /// Spins for at most `timeout` before giving up.
+    pub fn try_lock_for(&self, timeout: Duration) -> TryLockResult<Guard<'_, T, B>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            // a timeout that big is as good as forever.
+            None => Ok(self.lock()?),
+        }
+    }
+
+    /// Spins until `deadline` before giving up. Always tries at least once,
+    /// so a deadline in the past behaves like `try_lock`.
+    pub fn try_lock_until(&self, deadline: Instant) -> TryLockResult<Guard<'_, T, B>> {
+        let mut backoff = B::default();
+        let (mut waits, mut spins) = (0, 0);
+        loop {
+            // only swap when it looks free, so waiters don't keep stealing
+            // the cache line from whoever holds the lock.
+            if !self.raw.locked.load(Ordering::Relaxed)
+                && !self.raw.locked.swap(true, Ordering::Acquire)
+            {
+                self.raw.stats.record_acquire(waits, spins);
+                self.raw.order.locked();
+                return Ok(self.guard()?);
+            }
+            if Instant::now() >= deadline {
+                return Err(TryLockError::WouldBlock);
+            }
+            spins += u64::from(backoff.snooze());
+            waits += 1;
+        }
+    }
+
+    // only call this once we've taken the lock.
+    fn guard(&self) -> LockResult<Guard<'_, T, B>> {
+        let guard = Guard {
+            lock: self,
+            panicking: thread::panicking(),
+            timer: self.raw.stats.start_timer(),
+            _marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
         }
     }
 
-    pub fn lock(&self) -> Guard<T> {
-        while self.locked.swap(true, Ordering::Acquire) {
-            std::hint::spin_loop();
+    pub fn is_poisoned(&self) -> bool {
+        self.raw.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// For when the value has been checked (or fixed up) after a panic.
+    pub fn clear_poison(&self) {
+        self.raw.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> stats::Stats {
+        self.raw.stats.snapshot()
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let value = self.value.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
         }
-        Guard { lock: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::{PoisonError, TryLockError},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{
+        backoff::{Backoff, Exponential, Spin, SpinThenPark, SpinThenYield},
+        Guard, SpinLock,
+    };
+
+    #[test]
+    fn guard_is_only_sync_if_value_is() {
+        assert!(is_sync!(Guard<'static, u64>));
+        assert!(!is_sync!(Guard<'static, Cell<u64>>));
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = SpinLock::new(0);
+
+        let g = lock.lock().unwrap();
+        assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(g);
+
+        *lock.try_lock().unwrap() += 1;
+        assert_eq!(*lock.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn try_lock_for_times_out() {
+        let lock = SpinLock::new(());
+        let _g = lock.lock().unwrap();
+
+        let start = Instant::now();
+        assert!(lock.try_lock_for(Duration::from_millis(50)).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // a deadline in the past still gets one attempt.
+        assert!(lock.try_lock_until(Instant::now()).is_err());
+    }
+
+    #[test]
+    fn try_lock_for_waits_for_release() {
+        let lock = SpinLock::new(Vec::new());
+
+        thread::scope(|s| {
+            let g = lock.lock().unwrap();
+            s.spawn(|| {
+                let mut g = lock.try_lock_for(Duration::from_secs(5)).unwrap();
+                g.push(2);
+            });
+            thread::sleep(Duration::from_millis(20));
+            drop(g);
+        });
+
+        assert_eq!(lock.lock().unwrap().as_slice(), [2]);
+    }
+
+    fn counter_with<B: Backoff>() {
+        let lock = SpinLock::<_, B>::with_backoff(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        *lock.lock().unwrap() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner().unwrap(), 8000);
+    }
+
+    #[test]
+    fn backoff_strategies() {
+        counter_with::<Spin>();
+        counter_with::<Exponential>();
+        counter_with::<SpinThenYield>();
+        counter_with::<SpinThenPark>();
+    }
+
+    #[test]
+    fn poisoning() {
+        let lock = SpinLock::new(vec![1]);
+
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                let mut g = lock.lock().unwrap();
+                g.push(2);
+                panic!("oh no");
+            })
+            .join()
+        });
+        assert!(result.is_err());
+        assert!(lock.is_poisoned());
+
+        // the guard is still there for whoever wants to deal with it.
+        let g = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(g.as_slice(), [1, 2]);
+        drop(g);
+        assert!(matches!(lock.try_lock(), Err(TryLockError::Poisoned(_))));
+
+        lock.clear_poison();
+        lock.lock().unwrap().push(3);
+        assert_eq!(lock.into_inner().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn panicking_while_not_locked_doesnt_poison() {
+        let lock = SpinLock::new(0);
+
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                *lock.lock().unwrap() += 1;
+                panic!("oh no");
+            })
+            .join()
+        });
+        assert!(result.is_err());
+        assert!(!lock.is_poisoned());
+        assert!(lock.into_inner().is_ok());
+    }
+
+    #[test]
+    fn map() {
+        let lock = SpinLock::new((1, vec![2]));
+
+        let mut v = Guard::map(lock.lock().unwrap(), |(_, v)| v);
+        v.push(3);
+        assert!(lock.try_lock().is_err());
+        drop(v);
+
+        let g = lock.lock().unwrap();
+        let g = Guard::try_map(g, |(n, _)| (*n > 1).then_some(n))
+            .err()
+            .unwrap();
+        let mut n = Guard::try_map(g, |(n, _)| (*n == 1).then_some(n))
+            .ok()
+            .unwrap();
+        *n += 1;
+        drop(n);
+
+        assert_eq!(lock.into_inner().unwrap(), (2, vec![2, 3]));
+    }
+
+    #[test]
+    fn unlocked() {
+        let lock = SpinLock::new(Vec::new());
+
+        thread::scope(|s| {
+            let mut g = lock.lock().unwrap();
+            g.push(1);
+            Guard::unlocked(&mut g, || {
+                s.spawn(|| lock.lock().unwrap().push(2)).join().unwrap();
+            })
+            .unwrap();
+            g.push(3);
+        });
+
+        assert_eq!(lock.into_inner().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn poisoned_while_unlocked() {
+        let lock = SpinLock::new(0);
+
+        thread::scope(|s| {
+            let mut g = lock.lock().unwrap();
+            let result = Guard::unlocked(&mut g, || {
+                s.spawn(|| {
+                    let _g = lock.lock().unwrap();
+                    panic!("oh no");
+                })
+                .join()
+                .is_err()
+            });
+            // we still hold the lock, but get told what happened meanwhile.
+            assert!(result.err().unwrap().into_inner());
+            *g += 1;
+        });
+
+        assert_eq!(lock.into_inner().unwrap_err().into_inner(), 1);
     }
 }
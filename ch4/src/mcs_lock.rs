@@ -0,0 +1,158 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use crate::lock_order::LockOrder;
+
+/// An MCS queue lock. Waiters line up in a linked list and each one spins on
+/// the `locked` flag of its own node, so only the next in line gets its cache
+/// line invalidated when the lock is handed over.
+pub struct McsLock<T> {
+    // the last node in the queue, or null if the lock is free.
+    tail: AtomicPtr<Node>,
+    order: LockOrder,
+    value: UnsafeCell<T>,
+}
+
+struct Node {
+    next: AtomicPtr<Node>,
+    locked: AtomicBool,
+}
+
+unsafe impl<T> Sync for McsLock<T> where T: Send {}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            order: LockOrder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        self.order.before_lock();
+        // the node needs a stable address for as long as we're in the queue,
+        // so it lives on the heap and is owned by the guard.
+        let node = Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        }));
+
+        // Release so whoever comes after us sees an initialized node,
+        // Acquire to see what the last holder did if the lock was free.
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+        if !prev.is_null() {
+            // Safety: prev can't be freed until it has handed the lock to us,
+            // which it can't do before it finds us in its next pointer.
+            unsafe { (*prev).next.store(node, Ordering::Release) };
+            // Safety: node stays alive until our guard is dropped.
+            while unsafe { (*node).locked.load(Ordering::Acquire) } {
+                std::hint::spin_loop();
+            }
+        }
+        self.order.locked();
+        Guard { lock: self, node }
+    }
+}
+
+impl<T> Drop for McsLock<T> {
+    fn drop(&mut self) {
+        // a guard borrows the lock, so nothing can still be queued here.
+        debug_assert!(self.tail.get_mut().is_null());
+    }
+}
+
+pub struct Guard<'a, T> {
+    lock: &'a McsLock<T>,
+    node: *mut Node,
+}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we're at the front of the queue, so we hold the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we're at the front of the queue, so we hold the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.order.unlocked();
+        // Safety: the node is ours until we free it at the end of this function.
+        let node = unsafe { &*self.node };
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // nobody seems to be waiting, try to mark the lock as free.
+            if self
+                .lock
+                .tail
+                .compare_exchange(
+                    self.node,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                unsafe { drop(Box::from_raw(self.node)) };
+                return;
+            }
+            // someone swapped themselves into the tail but hasn't
+            // linked up to us yet. it won't take long.
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                std::hint::spin_loop();
+            }
+        }
+        // Safety: the next node is kept alive by its owner until it sees this store.
+        unsafe { (*next).locked.store(false, Ordering::Release) };
+        // Safety: the successor is done with our node once it's linked in.
+        unsafe { drop(Box::from_raw(self.node)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::McsLock;
+
+    #[test]
+    fn counter() {
+        let lock = McsLock::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..250 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(*lock.lock(), 1000);
+    }
+
+    #[test]
+    fn relock() {
+        let lock = McsLock::new(Vec::new());
+        lock.lock().push(1);
+        lock.lock().push(2);
+        assert_eq!(lock.lock().as_slice(), [1, 2]);
+    }
+}
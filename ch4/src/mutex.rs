@@ -0,0 +1,199 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        LockResult,
+    },
+};
+
+use crate::{condvar::Unlock, futex, lock_order::LockOrder};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+// locked, and there might be threads sleeping on the futex.
+const CONTENDED: u32 = 2;
+
+/// A mutex that puts waiters to sleep with a futex instead of spinning.
+/// Unlocking only makes a syscall when someone might be asleep.
+pub struct Mutex<T> {
+    state: AtomicU32,
+    order: LockOrder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            order: LockOrder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        self.raw_lock();
+        Guard::new(self)
+    }
+
+    fn raw_lock(&self) {
+        self.order.before_lock();
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        self.order.locked();
+    }
+
+    fn raw_unlock(&self) {
+        self.order.unlocked();
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex::wake_one(&self.state);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        self.order.locked();
+        Some(Guard::new(self))
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        // the lock is usually held for a short time, so spin a little before
+        // paying for a syscall. don't bother if others are already asleep.
+        let mut spins = 0;
+        while self.state.load(Ordering::Relaxed) == LOCKED && spins < 100 {
+            spins += 1;
+            std::hint::spin_loop();
+        }
+
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        // we don't know if anyone else is waiting, so take the lock as
+        // CONTENDED to make sure the next unlock wakes someone up.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex::wait(&self.state, CONTENDED);
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+pub struct Guard<'a, T> {
+    mutex: &'a Mutex<T>,
+    // only Sync if T is, &Mutex<T> alone would make it Sync for any T: Send.
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> Guard<'a, T> {
+    // only call this once we've taken the lock.
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self {
+            mutex,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: the very existance of this Guard
+        // guarantees we've exclusivly locked the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the very existance of this Guard
+        // guarantees we've exclusivly locked the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.raw_unlock();
+    }
+}
+
+impl<T> Unlock for Guard<'_, T> {
+    // this mutex doesn't poison, so it's always Ok.
+    fn unlocked<R>(&mut self, f: impl FnOnce() -> R) -> LockResult<R> {
+        struct Relock<'a, T>(&'a Mutex<T>);
+
+        impl<T> Drop for Relock<'_, T> {
+            fn drop(&mut self) {
+                self.0.raw_lock();
+            }
+        }
+
+        self.mutex.raw_unlock();
+        let _relock = Relock(self.mutex);
+        Ok(f())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, thread, time::Duration};
+
+    use super::{Guard, Mutex};
+
+    #[test]
+    fn guard_is_only_sync_if_value_is() {
+        assert!(is_sync!(Guard<'static, u64>));
+        assert!(!is_sync!(Guard<'static, Cell<u64>>));
+    }
+
+    #[test]
+    fn counter() {
+        let mutex = Mutex::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        *mutex.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(mutex.into_inner(), 8000);
+    }
+
+    #[test]
+    fn sleeping_waiter_is_woken() {
+        let mutex = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            let mut g = mutex.lock();
+            s.spawn(|| mutex.lock().push(2));
+            // long enough for the other thread to give up spinning.
+            thread::sleep(Duration::from_millis(50));
+            assert!(mutex.try_lock().is_none());
+            g.push(1);
+        });
+
+        assert_eq!(mutex.into_inner(), [1, 2]);
+    }
+}
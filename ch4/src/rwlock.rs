@@ -0,0 +1,440 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{futex, lock_order::LockOrder};
+
+// the state is a reader count in the high bits and a few flags in the low ones.
+const WRITER: u32 = 1;
+// held by an upgradable reader, which also counts as a reader.
+const UPGRADABLE: u32 = 2;
+// a writer (or upgrader) is waiting. only looked at with WriterPreferring.
+const WRITER_WAITING: u32 = 4;
+const READER: u32 = 8;
+
+/// Decides who goes first when readers and writers are both waiting.
+pub trait Policy {
+    const PREFER_WRITERS: bool;
+}
+
+/// New readers queue up behind a waiting writer, so writers can't starve.
+pub struct WriterPreferring;
+
+impl Policy for WriterPreferring {
+    const PREFER_WRITERS: bool = true;
+}
+
+/// Readers get in whenever no writer holds the lock. Better read throughput,
+/// but a steady stream of readers can keep a writer out forever.
+pub struct ReaderPreferring;
+
+impl Policy for ReaderPreferring {
+    const PREFER_WRITERS: bool = false;
+}
+
+/// How a blocked thread waits for the state to change.
+pub trait Wait {
+    /// Waits while `state` is still `expected`. May return spuriously.
+    fn wait(state: &AtomicU32, expected: u32);
+    fn wake_all(state: &AtomicU32);
+}
+
+/// Sleeps on a futex.
+pub struct Futex;
+
+impl Wait for Futex {
+    fn wait(state: &AtomicU32, expected: u32) {
+        futex::wait(state, expected);
+    }
+
+    fn wake_all(state: &AtomicU32) {
+        futex::wake_all(state);
+    }
+}
+
+/// Busy-waits, like `SpinLock`.
+pub struct Spinning;
+
+impl Wait for Spinning {
+    fn wait(_: &AtomicU32, _: u32) {
+        std::hint::spin_loop();
+    }
+
+    fn wake_all(_: &AtomicU32) {}
+}
+
+pub type SpinRwLock<T, P = WriterPreferring> = RwLock<T, P, Spinning>;
+
+/// A reader-writer lock. Any number of readers or a single writer can hold it
+/// at once, and one of the readers can hold an upgradable guard that can be
+/// turned into a write guard without letting another writer in first.
+pub struct RwLock<T, P = WriterPreferring, W = Futex> {
+    state: AtomicU32,
+    // threads that are (about to be) waiting, so unlocking can skip the
+    // wake syscall when nobody is.
+    waiters: AtomicU32,
+    // reads can be nested, but anything else while this thread already
+    // holds the lock would wait for itself.
+    order: LockOrder,
+    value: UnsafeCell<T>,
+    _policy: PhantomData<fn() -> (P, W)>,
+}
+
+unsafe impl<T, P, W> Sync for RwLock<T, P, W> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self::with_policy(value)
+    }
+}
+
+impl<T, P: Policy, W: Wait> RwLock<T, P, W> {
+    /// Like `new`, but for the other policies and the spinning variant,
+    /// e.g. `SpinRwLock::<_, ReaderPreferring>::with_policy(value)`.
+    pub const fn with_policy(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            order: LockOrder::new(),
+            value: UnsafeCell::new(value),
+            _policy: PhantomData,
+        }
+    }
+
+    fn readers_blocked(s: u32) -> bool {
+        s & WRITER != 0 || (P::PREFER_WRITERS && s & WRITER_WAITING != 0)
+    }
+
+    fn try_add_reader(&self, s: u32, extra: u32) -> Result<(), u32> {
+        let new = s.checked_add(READER + extra).expect("too many readers");
+        self.state
+            .compare_exchange_weak(s, new, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T, P, W> {
+        self.order.before_shared_lock();
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if !Self::readers_blocked(s) {
+                match self.try_add_reader(s, 0) {
+                    Ok(()) => {
+                        self.order.locked_shared();
+                        return ReadGuard { lock: self };
+                    }
+                    Err(e) => s = e,
+                }
+                continue;
+            }
+            self.wait(s);
+            s = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T, P, W>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while !Self::readers_blocked(s) {
+            match self.try_add_reader(s, 0) {
+                Ok(()) => {
+                    self.order.locked_shared();
+                    return Some(ReadGuard { lock: self });
+                }
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    /// Like `read`, but at most one thread can hold an upgradable guard at a time.
+    pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T, P, W> {
+        self.order.before_lock();
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if !Self::readers_blocked(s) && s & UPGRADABLE == 0 {
+                match self.try_add_reader(s, UPGRADABLE) {
+                    Ok(()) => {
+                        self.order.locked_shared();
+                        return UpgradableReadGuard { lock: self };
+                    }
+                    Err(e) => s = e,
+                }
+                continue;
+            }
+            self.wait(s);
+            s = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T, P, W> {
+        self.order.before_lock();
+        // nobody else may hold it, not even the upgradable reader.
+        self.lock_exclusive(0);
+        self.order.locked();
+        WriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T, P, W>> {
+        let s = self.state.load(Ordering::Relaxed);
+        if s >= READER || s & WRITER != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange(s, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        self.order.locked();
+        Some(WriteGuard { lock: self })
+    }
+
+    /// Waits until `held` is all that's left of the readers, and replaces it
+    /// with a writer.
+    fn lock_exclusive(&self, held: u32) {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s & !WRITER_WAITING == held {
+                // this clears WRITER_WAITING. other waiting writers set it
+                // again when they see we got in before them.
+                match self.state.compare_exchange_weak(
+                    s,
+                    WRITER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(e) => s = e,
+                }
+                continue;
+            }
+            if P::PREFER_WRITERS && s & WRITER_WAITING == 0 {
+                // keep new readers out while we wait.
+                if let Err(e) = self.state.compare_exchange_weak(
+                    s,
+                    s | WRITER_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    s = e;
+                    continue;
+                }
+                s |= WRITER_WAITING;
+            }
+            self.wait(s);
+            s = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    fn wait(&self, s: u32) {
+        // SeqCst on both sides (see `wake`): either the unlocker sees us in
+        // `waiters`, or we see its change to the state and don't wait.
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        if self.state.load(Ordering::SeqCst) == s {
+            W::wait(&self.state, s);
+        }
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Call after every change to the state that could unblock someone.
+    fn wake(&self) {
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            W::wake_all(&self.state);
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+pub struct ReadGuard<'a, T, P: Policy = WriterPreferring, W: Wait = Futex> {
+    lock: &'a RwLock<T, P, W>,
+}
+
+impl<T, P: Policy, W: Wait> Deref for ReadGuard<'_, T, P, W> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we're a reader, so there's no writer.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, P: Policy, W: Wait> Drop for ReadGuard<'_, T, P, W> {
+    fn drop(&mut self) {
+        self.lock.order.unlocked();
+        self.lock.state.fetch_sub(READER, Ordering::SeqCst);
+        self.lock.wake();
+    }
+}
+
+pub struct UpgradableReadGuard<'a, T, P: Policy = WriterPreferring, W: Wait = Futex> {
+    lock: &'a RwLock<T, P, W>,
+}
+
+impl<'a, T, P: Policy, W: Wait> UpgradableReadGuard<'a, T, P, W> {
+    /// Waits for the other readers to leave and turns this into a write guard.
+    /// No writer can get in between, since we're still a reader until then.
+    pub fn upgrade(guard: Self) -> WriteGuard<'a, T, P, W> {
+        let lock = guard.lock;
+        // our reader is taken over by the write guard, and so is our place
+        // in the lock order, so no hooks here.
+        mem::forget(guard);
+        lock.lock_exclusive(READER | UPGRADABLE);
+        WriteGuard { lock }
+    }
+}
+
+impl<T, P: Policy, W: Wait> Deref for UpgradableReadGuard<'_, T, P, W> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we're a reader, so there's no writer.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, P: Policy, W: Wait> Drop for UpgradableReadGuard<'_, T, P, W> {
+    fn drop(&mut self) {
+        self.lock.order.unlocked();
+        self.lock
+            .state
+            .fetch_sub(READER + UPGRADABLE, Ordering::SeqCst);
+        self.lock.wake();
+    }
+}
+
+pub struct WriteGuard<'a, T, P: Policy = WriterPreferring, W: Wait = Futex> {
+    lock: &'a RwLock<T, P, W>,
+}
+
+impl<T, P: Policy, W: Wait> Deref for WriteGuard<'_, T, P, W> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: the very existance of this Guard
+        // guarantees we've exclusivly locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, P: Policy, W: Wait> DerefMut for WriteGuard<'_, T, P, W> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the very existance of this Guard
+        // guarantees we've exclusivly locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T, P: Policy, W: Wait> Drop for WriteGuard<'_, T, P, W> {
+    fn drop(&mut self) {
+        self.lock.order.unlocked();
+        // leave WRITER_WAITING alone, another writer might have set it.
+        self.lock.state.fetch_and(!WRITER, Ordering::SeqCst);
+        self.lock.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{
+        Policy, ReaderPreferring, RwLock, SpinRwLock, UpgradableReadGuard, Wait, WriterPreferring,
+    };
+
+    fn counter<P: Policy, W: Wait>() {
+        let lock = RwLock::<_, P, W>::with_policy(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        *lock.write() += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        assert!(*lock.read() <= 2000);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner(), 2000);
+    }
+
+    #[test]
+    fn readers_and_writers() {
+        counter::<WriterPreferring, super::Futex>();
+        counter::<ReaderPreferring, super::Futex>();
+        counter::<WriterPreferring, super::Spinning>();
+        counter::<ReaderPreferring, super::Spinning>();
+    }
+
+    #[test]
+    fn shared_reads() {
+        let lock = RwLock::new(5);
+        let a = lock.read();
+        let b = lock.read();
+        assert_eq!(*a + *b, 10);
+        assert!(lock.try_write().is_none());
+        drop((a, b));
+        assert!(lock.try_write().is_some());
+    }
+
+    #[test]
+    fn writer_preferring_blocks_new_readers() {
+        let lock = RwLock::new(0);
+
+        thread::scope(|s| {
+            let r = lock.read();
+            s.spawn(|| *lock.write() += 1);
+            // give the writer time to start waiting.
+            thread::sleep(Duration::from_millis(50));
+            assert!(lock.try_read().is_none());
+            drop(r);
+        });
+
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn reader_preferring_lets_readers_in() {
+        let lock = SpinRwLock::<_, ReaderPreferring>::with_policy(0);
+
+        thread::scope(|s| {
+            let r = lock.read();
+            s.spawn(|| *lock.write() += 1);
+            thread::sleep(Duration::from_millis(50));
+            assert!(lock.try_read().is_some());
+            drop(r);
+        });
+
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn upgrade() {
+        let lock = RwLock::new(Vec::new());
+
+        thread::scope(|s| {
+            let u = lock.upgradable_read();
+            // plain readers can still get in, a second upgradable or a writer can't.
+            assert!(lock.try_read().is_some());
+            assert!(lock.try_write().is_none());
+
+            let r = lock.read();
+            s.spawn(|| lock.write().push(2));
+            thread::sleep(Duration::from_millis(20));
+            // the upgrade has to wait for `r`, and the writer can't sneak in.
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(r);
+            });
+            let mut w = UpgradableReadGuard::upgrade(u);
+            assert!(w.is_empty());
+            w.push(1);
+        });
+
+        assert_eq!(lock.into_inner(), [1, 2]);
+    }
+}
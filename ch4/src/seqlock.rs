@@ -0,0 +1,167 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// A sequence lock for small `Copy` values that are read much more often
+/// than they're written, like a handful of statistics that belong together.
+///
+/// Readers never block and never write to shared memory. They copy the value
+/// and check the sequence number didn't change while they did, retrying if it
+/// did. The sequence number is odd while a write is in progress, which is
+/// also what keeps writers out of each other's way.
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SeqLock<T> where T: Copy + Send {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the value. Spins while a write is in progress.
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Like `read`, but gives up instead of retrying if a writer got in the way.
+    pub fn try_read(&self) -> Option<T> {
+        let before = self.seq.load(Ordering::Acquire);
+        if before & 1 != 0 {
+            return None;
+        }
+        // this races with writers, so the copy might be torn. MaybeUninit
+        // because a torn copy isn't necessarily a valid T, and volatile so
+        // the compiler doesn't assume nobody else is writing.
+        let value = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+        // keep the copy from being moved after the second load.
+        fence(Ordering::Acquire);
+        let after = self.seq.load(Ordering::Relaxed);
+        // Safety: the sequence number didn't change, so no write overlapped our copy.
+        (before == after).then(|| unsafe { value.assume_init() })
+    }
+
+    pub fn write(&self, value: T) {
+        self.update(|v| *v = value);
+    }
+
+    /// Changes the value in place. Readers see either all of the change or none of it.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        struct EndWrite<'a>(&'a AtomicUsize, usize);
+
+        impl Drop for EndWrite<'_> {
+            fn drop(&mut self) {
+                self.0.store(self.1, Ordering::Release);
+            }
+        }
+
+        let seq = self.begin_write();
+        // if f panics nothing was written back, so the old number is still right.
+        let mut end = EndWrite(&self.seq, seq);
+        // Safety: we own the odd sequence number, so no one else is writing.
+        // copy it out and back in, so f never sees memory readers are copying.
+        unsafe {
+            let mut value = ptr::read_volatile(self.value.get());
+            f(&mut value);
+            ptr::write_volatile(self.value.get(), value);
+        }
+        end.1 = seq.wrapping_add(2);
+    }
+
+    // makes the sequence number odd and returns what it was before.
+    fn begin_write(&self) -> usize {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 != 0 {
+                std::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.seq.compare_exchange_weak(
+                seq,
+                seq.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(e) => seq = e,
+            }
+        }
+        // make sure readers that see our writes also see the odd number.
+        fence(Ordering::Release);
+        seq
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::SeqLock;
+
+    #[derive(Clone, Copy)]
+    struct Stats {
+        num_done: u64,
+        total_time: u64,
+        max_time: u64,
+    }
+
+    #[test]
+    fn reads_are_never_torn() {
+        let stats = SeqLock::new(Stats {
+            num_done: 0,
+            total_time: 0,
+            max_time: 0,
+        });
+
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        stats.update(|s| {
+                            s.num_done += 1;
+                            s.total_time += 3;
+                            s.max_time = s.max_time.max(s.num_done);
+                        });
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    let s = stats.read();
+                    assert_eq!(s.total_time, s.num_done * 3);
+                    assert_eq!(s.max_time, s.num_done);
+                }
+            });
+        });
+
+        let s = stats.into_inner();
+        assert_eq!(s.num_done, 2000);
+        assert_eq!(s.total_time, 6000);
+    }
+
+    #[test]
+    fn write_and_read() {
+        let lock = SeqLock::new((1, 2));
+        assert_eq!(lock.try_read(), Some((1, 2)));
+        lock.write((3, 4));
+        assert_eq!(lock.read(), (3, 4));
+    }
+}
@@ -0,0 +1,164 @@
+//! Contention statistics for `SpinLock`, behind the `lock-stats` feature.
+//!
+//! Without the feature, `LockStats` and `HoldTimer` are empty and every
+//! method on them does nothing, so the lock doesn't pay for them.
+
+#[cfg(feature = "lock-stats")]
+pub use enabled::{LockStats, Stats};
+
+#[cfg(not(feature = "lock-stats"))]
+pub use disabled::LockStats;
+
+#[cfg(feature = "lock-stats")]
+pub type HoldTimer = std::time::Instant;
+
+#[cfg(not(feature = "lock-stats"))]
+pub type HoldTimer = disabled::HoldTimer;
+
+#[cfg(feature = "lock-stats")]
+mod enabled {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering::Relaxed},
+        time::Instant,
+    };
+
+    /// Bucket `i` counts hold times under `2^i` microseconds,
+    /// the last bucket counts everything longer than that.
+    pub const HOLD_BUCKETS: usize = 16;
+
+    pub struct LockStats {
+        acquisitions: AtomicU64,
+        contended: AtomicU64,
+        total_spins: AtomicU64,
+        max_spins: AtomicU64,
+        hold_times: [AtomicU64; HOLD_BUCKETS],
+    }
+
+    /// A snapshot of `LockStats`. The counters are read one by one while the
+    /// lock might be in use, so they can be a little out of sync.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Stats {
+        pub acquisitions: u64,
+        /// Acquisitions that had to wait at least once.
+        pub contended: u64,
+        /// Spin loop iterations while waiting, summed over all acquisitions.
+        /// Time spent yielding or parked isn't counted.
+        pub total_spins: u64,
+        /// The most spin loop iterations a single acquisition needed.
+        pub max_spins: u64,
+        /// How long the lock was held, see `HOLD_BUCKETS`.
+        pub hold_times: [u64; HOLD_BUCKETS],
+    }
+
+    impl LockStats {
+        pub const fn new() -> Self {
+            Self {
+                acquisitions: AtomicU64::new(0),
+                contended: AtomicU64::new(0),
+                total_spins: AtomicU64::new(0),
+                max_spins: AtomicU64::new(0),
+                hold_times: [const { AtomicU64::new(0) }; HOLD_BUCKETS],
+            }
+        }
+
+        pub fn start_timer(&self) -> Instant {
+            Instant::now()
+        }
+
+        /// `waits` is the number of failed attempts, `spins` how many times
+        /// the backoff spun between them.
+        pub fn record_acquire(&self, waits: u64, spins: u64) {
+            self.acquisitions.fetch_add(1, Relaxed);
+            if waits > 0 {
+                self.contended.fetch_add(1, Relaxed);
+                self.total_spins.fetch_add(spins, Relaxed);
+                self.max_spins.fetch_max(spins, Relaxed);
+            }
+        }
+
+        pub fn record_release(&self, acquired: &Instant) {
+            let micros = acquired.elapsed().as_micros() as u64;
+            // the number of bits needed for micros is the first bucket it fits under.
+            let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+            self.hold_times[bucket.min(HOLD_BUCKETS - 1)].fetch_add(1, Relaxed);
+        }
+
+        pub fn snapshot(&self) -> Stats {
+            Stats {
+                acquisitions: self.acquisitions.load(Relaxed),
+                contended: self.contended.load(Relaxed),
+                total_spins: self.total_spins.load(Relaxed),
+                max_spins: self.max_spins.load(Relaxed),
+                hold_times: self.hold_times.each_ref().map(|n| n.load(Relaxed)),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "lock-stats"))]
+mod disabled {
+    pub struct LockStats;
+
+    #[derive(Clone, Copy)]
+    pub struct HoldTimer;
+
+    impl LockStats {
+        pub const fn new() -> Self {
+            Self
+        }
+
+        pub fn start_timer(&self) -> HoldTimer {
+            HoldTimer
+        }
+
+        pub fn record_acquire(&self, _waits: u64, _spins: u64) {}
+
+        pub fn record_release(&self, _acquired: &HoldTimer) {}
+    }
+}
+
+#[cfg(all(test, feature = "lock-stats"))]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::enabled::HOLD_BUCKETS;
+    use crate::SpinLock;
+
+    #[test]
+    fn counts_acquisitions_and_hold_times() {
+        let lock = SpinLock::new(0);
+
+        *lock.lock().unwrap() += 1;
+        *lock.try_lock().unwrap() += 1;
+        {
+            let _g = lock.lock().unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        let stats = lock.stats();
+        assert_eq!(stats.acquisitions, 3);
+        assert_eq!(stats.contended, 0);
+        assert_eq!(stats.hold_times.iter().sum::<u64>(), 3);
+        // 2ms is just under 2^11 microseconds, so bucket 11 if the sleep was exact.
+        assert_eq!(stats.hold_times[..11].iter().sum::<u64>(), 2);
+        assert_eq!(stats.hold_times[11..HOLD_BUCKETS].iter().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn counts_contention() {
+        let lock = SpinLock::new(());
+
+        thread::scope(|s| {
+            let g = lock.lock().unwrap();
+            s.spawn(|| drop(lock.lock().unwrap()));
+            thread::sleep(Duration::from_millis(20));
+            drop(g);
+        });
+
+        let stats = lock.stats();
+        assert_eq!(stats.acquisitions, 2);
+        assert_eq!(stats.contended, 1);
+        assert!(stats.max_spins > 0);
+        assert_eq!(stats.total_spins, stats.max_spins);
+    }
+}
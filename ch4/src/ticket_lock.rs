@@ -0,0 +1,141 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::lock_order::LockOrder;
+
+/// A fair spin lock: every thread takes a ticket and waits until its number
+/// is being served, so the lock is handed out in the order it was asked for.
+pub struct TicketLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    order: LockOrder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for TicketLock<T> where T: Send {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            order: LockOrder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        // relocking would wait behind our own ticket forever.
+        self.order.before_lock();
+        // the counters wrap around, which is fine as long as there are
+        // fewer than u32::MAX threads waiting at once.
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            std::hint::spin_loop();
+        }
+        self.order.locked();
+        Guard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Number of threads holding or waiting for the lock. It's a snapshot
+    /// that can be out of date by the time it's returned, only use it for
+    /// diagnostics.
+    pub fn queue_len(&self) -> u32 {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        let next = self.next_ticket.load(Ordering::Relaxed);
+        next.wrapping_sub(serving)
+    }
+}
+
+pub struct Guard<'a, T> {
+    lock: &'a TicketLock<T>,
+    // only Sync if T is, &TicketLock<T> alone would make it Sync for any T: Send.
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we're the one being served, nobody else can be in here.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we're the one being served, nobody else can be in here.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.order.unlocked();
+        // only the holder ever writes now_serving, so no read-modify-write needed.
+        let serving = self.lock.now_serving.load(Ordering::Relaxed);
+        self.lock
+            .now_serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, thread, time::Duration};
+
+    use super::{Guard, TicketLock};
+
+    #[test]
+    fn guard_is_only_sync_if_value_is() {
+        assert!(is_sync!(Guard<'static, u64>));
+        assert!(!is_sync!(Guard<'static, Cell<u64>>));
+    }
+
+    #[test]
+    fn counter() {
+        let lock = TicketLock::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..250 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(*lock.lock(), 1000);
+        assert_eq!(lock.queue_len(), 0);
+    }
+
+    #[test]
+    fn fifo_order() {
+        let lock = TicketLock::new(Vec::new());
+
+        thread::scope(|s| {
+            let g = lock.lock();
+            for i in 1..=3 {
+                // wait for the previous thread to have taken its ticket.
+                while lock.queue_len() != i {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                let lock = &lock;
+                s.spawn(move || lock.lock().push(i));
+            }
+            while lock.queue_len() != 4 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            drop(g);
+        });
+
+        assert_eq!(lock.lock().as_slice(), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::SendError;
+
+/// Creates a channel where every receiver gets a clone of every message.
+/// Only the last `capacity` messages are kept, a receiver that falls further
+/// behind than that skips ahead and gets told how many it missed.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be at least 1");
+    let a = Arc::new(Channel {
+        state: Mutex::new(State {
+            ring: VecDeque::with_capacity(capacity),
+            first: 0,
+            capacity,
+            senders: 1,
+            receivers: 1,
+        }),
+        item_ready: Condvar::new(),
+    });
+
+    let receiver = Receiver {
+        channel: a.clone(),
+        next: 0,
+    };
+    (Sender { channel: a }, receiver)
+}
+
+struct Channel<T> {
+    state: Mutex<State<T>>,
+    item_ready: Condvar,
+}
+
+struct State<T> {
+    ring: VecDeque<T>,
+    // the position of ring[0] among all messages ever sent.
+    first: u64,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+}
+
+impl<T> State<T> {
+    // the position the next message will get.
+    fn end(&self) -> u64 {
+        self.first + self.ring.len() as u64
+    }
+
+    // the message at `next` if it's still kept, moving `next` past it.
+    fn take(&self, next: &mut u64) -> Result<T, TryRecvError>
+    where
+        T: Clone,
+    {
+        if *next < self.first {
+            let missed = self.first - *next;
+            *next = self.first;
+            return Err(TryRecvError::Lagged(missed));
+        }
+        if *next == self.end() {
+            return Err(if self.senders == 0 {
+                TryRecvError::Disconnected
+            } else {
+                TryRecvError::Empty
+            });
+        }
+        let message = self.ring[(*next - self.first) as usize].clone();
+        *next += 1;
+        Ok(message)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// The receiver fell behind and missed this many messages. Receiving
+    /// again continues with the oldest message still kept.
+    Lagged(u64),
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Lagged(u64),
+    Disconnected,
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Never blocks, if the ring is full the oldest message is dropped.
+    /// Gives the message back if there are no receivers.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut b = self.channel.state.lock().unwrap();
+        if b.receivers == 0 {
+            return Err(SendError(message));
+        }
+        if b.ring.len() == b.capacity {
+            b.ring.pop_front();
+            b.first += 1;
+        }
+        b.ring.push_back(message);
+        drop(b);
+        self.channel.item_ready.notify_all();
+        Ok(())
+    }
+
+    /// A new receiver that gets every message sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut b = self.channel.state.lock().unwrap();
+        b.receivers += 1;
+        Receiver {
+            channel: self.channel.clone(),
+            next: b.end(),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().senders += 1;
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut b = self.channel.state.lock().unwrap();
+        b.senders -= 1;
+        if b.senders == 0 {
+            drop(b);
+            self.channel.item_ready.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    // the position of the next message this receiver gets.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Blocks until there's a message this receiver hasn't seen. Fails once
+    /// every sender is gone and it has seen all of them.
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        let mut b = self.channel.state.lock().unwrap();
+        loop {
+            match b.take(&mut self.next) {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+                Err(TryRecvError::Empty) => b = self.channel.item_ready.wait(b).unwrap(),
+            }
+        }
+    }
+
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        let b = self.channel.state.lock().unwrap();
+        b.take(&mut self.next)
+    }
+}
+
+/// The clone picks up where this receiver is.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().receivers += 1;
+        Self {
+            channel: self.channel.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.state.lock().unwrap().receivers -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{channel, RecvError, TryRecvError};
+    use crate::SendError;
+
+    #[test]
+    fn every_receiver_gets_every_message() {
+        let (tx, rx) = channel(4);
+
+        thread::scope(|s| {
+            for mut rx in [rx.clone(), rx.clone(), rx] {
+                s.spawn(move || {
+                    let mut received = Vec::new();
+                    while let Ok(message) = rx.receive() {
+                        received.push(message);
+                    }
+                    assert_eq!(received, [1, 2, 3]);
+                });
+            }
+            for i in 1..=3 {
+                tx.send(i).unwrap();
+            }
+            drop(tx);
+        });
+    }
+
+    #[test]
+    fn slow_receiver_lags() {
+        let (tx, mut rx) = channel(2);
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        assert_eq!(rx.receive(), Err(RecvError::Lagged(3)));
+        assert_eq!(rx.receive(), Ok(3));
+        assert_eq!(rx.receive(), Ok(4));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn subscribe_sees_later_messages() {
+        let (tx, mut rx) = channel(4);
+        tx.send("before").unwrap();
+        let mut late = tx.subscribe();
+        tx.send("after").unwrap();
+        drop(tx);
+
+        assert_eq!(rx.receive(), Ok("before"));
+        assert_eq!(rx.receive(), Ok("after"));
+        assert_eq!(late.receive(), Ok("after"));
+        assert_eq!(late.receive(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn send_fails_without_receivers() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+}
@@ -1,42 +1,452 @@
+// The channels here are a library in all but name; main doesn't use them.
+#![allow(dead_code)]
+
 use std::{
     collections::VecDeque,
-    sync::{Condvar, Mutex},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+mod broadcast;
+mod mpsc;
 mod one_shot;
+mod select;
+mod spsc;
 mod typed_channel;
+mod typed_error;
 mod typed_lifetimes;
+mod watch;
 
 fn main() {
     println!("Hello, world!");
 }
 
 pub struct Channel<T> {
-    queue: Mutex<VecDeque<T>>,
-    item_ready: Condvar,
+    state: Mutex<State<T>>,
+    not_full: Condvar,
+    // None for unbounded channels.
+    capacity: Option<usize>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    // receivers blocked in receive, in the order they started waiting.
+    // only the first one takes messages, so they're handed out in turn.
+    waiting: VecDeque<(u64, Thread)>,
+    next_waiter: u64,
+    // threads in a Select that includes this channel.
+    selecting: Vec<Thread>,
+    // a Channel used directly counts as one of each, so it never disconnects.
+    senders: usize,
+    receivers: usize,
+}
+
+impl<T> State<T> {
+    fn wake_first(&self) {
+        if let Some((_, thread)) = self.waiting.front() {
+            thread.unpark();
+        }
+    }
+
+    fn wake_selecting(&self) {
+        for thread in &self.selecting {
+            thread.unpark();
+        }
+    }
+
+    fn wake_all(&self) {
+        for (_, thread) in &self.waiting {
+            thread.unpark();
+        }
+        self.wake_selecting();
+    }
+}
+
+/// The receiver is gone, so the message is handed back.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// Every sender is gone and there are no messages left.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
 }
 
 impl<T> Channel<T> {
     pub fn new() -> Self {
+        Self::with_capacity(None)
+    }
+
+    /// A channel that holds at most `capacity` messages. Sending to a full
+    /// channel blocks until a message is received.
+    pub fn bounded(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self::with_capacity(Some(capacity))
+    }
+
+    fn with_capacity(capacity: Option<usize>) -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
-            item_ready: Condvar::new(),
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                waiting: VecDeque::new(),
+                next_waiter: 0,
+                selecting: Vec::new(),
+                senders: 1,
+                receivers: 1,
+            }),
+            not_full: Condvar::new(),
+            capacity,
         }
     }
 
+    fn is_full(&self, queue: &VecDeque<T>) -> bool {
+        self.capacity.is_some_and(|cap| queue.len() >= cap)
+    }
+
+    fn push(&self, mut b: MutexGuard<State<T>>, message: T) {
+        b.queue.push_back(message);
+        b.wake_first();
+        b.wake_selecting();
+    }
+
     pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
-        self.item_ready.notify_one();
+        // only a Receiver going away can make this fail.
+        let _ = self.checked_send(message);
+    }
+
+    fn checked_send(&self, message: T) -> Result<(), SendError<T>> {
+        let b = self.state.lock().unwrap();
+        let b = self
+            .not_full
+            .wait_while(b, |s| s.receivers > 0 && self.is_full(&s.queue))
+            .unwrap();
+        if b.receivers == 0 {
+            return Err(SendError(message));
+        }
+        self.push(b, message);
+        Ok(())
+    }
+
+    /// Gives the message back instead of blocking if the channel is full.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let b = self.state.lock().unwrap();
+        if b.receivers == 0 {
+            return Err(TrySendError::Disconnected(message));
+        }
+        if self.is_full(&b.queue) {
+            return Err(TrySendError::Full(message));
+        }
+        self.push(b, message);
+        Ok(())
     }
 
+    /// Gives the message back if the channel is still full after `timeout`.
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        let b = self.state.lock().unwrap();
+        let (b, _) = self
+            .not_full
+            .wait_timeout_while(b, timeout, |s| s.receivers > 0 && self.is_full(&s.queue))
+            .unwrap();
+        if b.receivers == 0 {
+            return Err(SendTimeoutError::Disconnected(message));
+        }
+        if self.is_full(&b.queue) {
+            return Err(SendTimeoutError::Timeout(message));
+        }
+        self.push(b, message);
+        Ok(())
+    }
+
+    /// Blocks until there's a message. When several threads are waiting,
+    /// messages go to them in the order they started waiting.
     pub fn receive(&self) -> T {
-        let mut b = self.queue.lock().unwrap();
+        match self.checked_receive() {
+            Ok(message) => message,
+            Err(Disconnected) => unreachable!("a Channel used directly has no senders to drop"),
+        }
+    }
+
+    fn checked_receive(&self) -> Result<T, Disconnected> {
+        self.receive_until(None).map_err(|e| match e {
+            RecvTimeoutError::Disconnected => Disconnected,
+            RecvTimeoutError::Timeout => unreachable!("there's no deadline"),
+        })
+    }
+
+    /// Returns right away if there's no message. Doesn't wait its turn,
+    /// so it can take a message a blocked receiver was about to get.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        let mut b = self.state.lock().unwrap();
+        match b.queue.pop_front() {
+            Some(message) => {
+                drop(b);
+                self.not_full.notify_one();
+                Ok(message)
+            }
+            None if b.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
 
-        loop {
-            if let Some(message) = b.pop_front() {
-                return message;
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // a timeout too big for an Instant is as good as forever.
+        self.receive_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Waits for a message until `deadline`. Always checks at least once,
+    /// so a deadline in the past behaves like `try_receive`.
+    pub fn receive_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(Some(deadline))
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut b = self.state.lock().unwrap();
+
+        // nobody's waiting, so there's no one to cut in front of.
+        if b.waiting.is_empty() {
+            if let Some(message) = b.queue.pop_front() {
+                drop(b);
+                self.not_full.notify_one();
+                return Ok(message);
             }
-            b = self.item_ready.wait(b).unwrap();
+        }
+
+        let id = b.next_waiter;
+        b.next_waiter += 1;
+        b.waiting.push_back((id, thread::current()));
+
+        let result = loop {
+            if b.waiting.front().is_some_and(|w| w.0 == id) {
+                if let Some(message) = b.queue.pop_front() {
+                    break Ok(message);
+                }
+            }
+            if b.queue.is_empty() && b.senders == 0 {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if !timeout.is_zero() => Some(timeout),
+                    _ => break Err(RecvTimeoutError::Timeout),
+                },
+                None => None,
+            };
+            drop(b);
+            // unparks aren't lost, if one came in since we unlocked this returns right away.
+            match timeout {
+                Some(timeout) => thread::park_timeout(timeout),
+                None => thread::park(),
+            }
+            b = self.state.lock().unwrap();
+        };
+
+        let i = b.waiting.iter().position(|w| w.0 == id).unwrap();
+        b.waiting.remove(i);
+        // whether we took one or gave up, it's the next one's turn. once
+        // the senders are gone, the rest might have nothing left to wait for.
+        if b.senders == 0 {
+            b.wake_all();
+        } else if !b.queue.is_empty() {
+            b.wake_first();
+        }
+        drop(b);
+        if result.is_ok() {
+            self.not_full.notify_one();
+        }
+        result
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates an unbounded channel split into a sending and a receiving half.
+/// Unlike a shared `Channel`, each half notices when the other side is gone.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    split(Channel::new())
+}
+
+/// Like `channel`, but holds at most `capacity` messages.
+pub fn bounded_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    split(Channel::bounded(capacity))
+}
+
+// the channel already counts one sender and one receiver.
+fn split<T>(channel: Channel<T>) -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(channel);
+    let sender = Sender {
+        channel: channel.clone(),
+    };
+    (sender, Receiver { channel })
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Can be cloned to spread the messages over several threads.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Blocks while the channel is full. Gives the message back if the
+    /// receiver is gone.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.channel.checked_send(message)
+    }
+
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.channel.try_send(message)
+    }
+
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.channel.send_timeout(message, timeout)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().senders += 1;
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut b = self.channel.state.lock().unwrap();
+        b.senders -= 1;
+        if b.senders == 0 {
+            // nothing else is coming, wake the receivers so they can find out.
+            b.wake_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until there's a message. Fails once every sender is gone and
+    /// all messages they sent have been received.
+    pub fn receive(&self) -> Result<T, Disconnected> {
+        self.channel.checked_receive()
+    }
+
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        self.channel.try_receive()
+    }
+
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.channel.receive_timeout(timeout)
+    }
+
+    pub fn receive_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.channel.receive_deadline(deadline)
+    }
+
+    /// Blocks for each message, ending once every sender is gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    /// Only the messages that are already there, never blocks.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+}
+
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+pub struct TryIter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_receive().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().receivers += 1;
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut b = self.channel.state.lock().unwrap();
+        b.receivers -= 1;
+        if b.receivers == 0 {
+            drop(b);
+            // wake senders blocked on a full channel, nobody will make room.
+            self.channel.not_full.notify_all();
         }
     }
 }
@@ -45,10 +455,13 @@ impl<T> Channel<T> {
 mod tests {
     use std::{
         thread::{self, sleep},
-        time::Duration,
+        time::{Duration, Instant},
     };
 
-    use crate::Channel;
+    use crate::{
+        bounded_channel, channel, Channel, Disconnected, Receiver, RecvTimeoutError, SendError,
+        SendTimeoutError, Sender, TryRecvError, TrySendError,
+    };
 
     #[test]
     fn test_channel() {
@@ -68,4 +481,253 @@ mod tests {
             });
         });
     }
+
+    #[test]
+    fn bounded_channel_blocks_when_full() {
+        let channel = Channel::bounded(1);
+
+        channel.send(1);
+        assert_eq!(channel.try_send(2), Err(TrySendError::Full(2)));
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                sleep(Duration::from_millis(20));
+                assert_eq!(channel.receive(), 1);
+            });
+            // blocks until the other thread makes room.
+            channel.send(2);
+        });
+
+        assert_eq!(channel.receive(), 2);
+    }
+
+    #[test]
+    fn send_timeout() {
+        let channel = Channel::bounded(1);
+        channel.send("first");
+
+        let start = Instant::now();
+        let result = channel.send_timeout("second", Duration::from_millis(20));
+        assert_eq!(result, Err(SendTimeoutError::Timeout("second")));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        assert_eq!(channel.receive(), "first");
+        assert_eq!(
+            channel.send_timeout("third", Duration::from_millis(20)),
+            Ok(())
+        );
+        assert_eq!(channel.receive(), "third");
+    }
+
+    #[test]
+    fn try_receive() {
+        let channel = Channel::new();
+        assert_eq!(channel.try_receive(), Err(TryRecvError::Empty));
+        channel.send(1);
+        assert_eq!(channel.try_receive(), Ok(1));
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let channel = Channel::new();
+
+        let start = Instant::now();
+        let result = channel.receive_timeout(Duration::from_millis(20));
+        assert_eq!(result, Err(RecvTimeoutError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                sleep(Duration::from_millis(10));
+                channel.send("late");
+            });
+            assert_eq!(channel.receive_timeout(Duration::from_secs(5)), Ok("late"));
+        });
+
+        // a deadline in the past still checks once.
+        channel.send("ready");
+        assert_eq!(channel.receive_deadline(Instant::now()), Ok("ready"));
+    }
+
+    #[test]
+    fn receiver_disconnects_after_draining() {
+        let (tx, rx) = channel();
+        let tx2 = tx.clone();
+
+        thread::scope(|s| {
+            s.spawn(move || tx.send(1).unwrap());
+            s.spawn(move || tx2.send(2).unwrap());
+        });
+
+        let mut received = [rx.receive().unwrap(), rx.receive().unwrap()];
+        received.sort();
+        assert_eq!(received, [1, 2]);
+        assert_eq!(rx.receive(), Err(Disconnected));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.receive_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn dropping_last_sender_wakes_receiver() {
+        let (tx, rx) = channel::<()>();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                sleep(Duration::from_millis(10));
+                drop(tx);
+            });
+            assert_eq!(rx.receive(), Err(Disconnected));
+        });
+    }
+
+    #[test]
+    fn send_fails_without_receiver() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send("lost"), Err(SendError("lost")));
+        assert_eq!(tx.try_send("lost"), Err(TrySendError::Disconnected("lost")));
+    }
+
+    #[test]
+    fn dropping_receiver_wakes_blocked_sender() {
+        let (tx, rx) = bounded_channel(1);
+        tx.send(1).unwrap();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                sleep(Duration::from_millis(10));
+                drop(rx);
+            });
+            // blocks on the full channel until the receiver goes away.
+            assert_eq!(tx.send(2), Err(SendError(2)));
+        });
+        assert_eq!(
+            tx.send_timeout(3, Duration::from_secs(5)),
+            Err(SendTimeoutError::Disconnected(3))
+        );
+    }
+
+    #[test]
+    fn iterate_until_disconnected() {
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..5 {
+                    tx.send(i).unwrap();
+                }
+            });
+            assert_eq!(rx.iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        });
+    }
+
+    #[test]
+    fn try_iter_drains_without_blocking() {
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1, 2]);
+        // the sender is still around, but try_iter doesn't wait for it.
+        assert_eq!(rx.try_iter().next(), None);
+
+        tx.send(3).unwrap();
+        drop(tx);
+        let mut received = Vec::new();
+        for message in rx {
+            received.push(message);
+        }
+        assert_eq!(received, [3]);
+    }
+
+    // every message is received exactly once, however many threads are on each side.
+    fn stress(tx: Sender<usize>, rx: Receiver<usize>) {
+        const PRODUCERS: usize = 3;
+        const CONSUMERS: usize = 3;
+        const PER_PRODUCER: usize = 300;
+
+        let mut received = thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let tx = tx.clone();
+                s.spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        tx.send(p * PER_PRODUCER + i).unwrap();
+                    }
+                });
+            }
+            drop(tx);
+
+            let consumers: Vec<_> = (0..CONSUMERS)
+                .map(|_| {
+                    let rx = rx.clone();
+                    s.spawn(move || rx.into_iter().collect::<Vec<_>>())
+                })
+                .collect();
+            drop(rx);
+            consumers
+                .into_iter()
+                .flat_map(|c| c.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        received.sort();
+        assert_eq!(received, (0..PRODUCERS * PER_PRODUCER).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stress_unbounded() {
+        let (tx, rx) = channel();
+        stress(tx, rx);
+    }
+
+    #[test]
+    fn stress_bounded() {
+        let (tx, rx) = bounded_channel(4);
+        stress(tx, rx);
+    }
+
+    #[test]
+    fn receivers_take_turns() {
+        let (tx, rx) = channel();
+        let waiting = |n| {
+            while rx.channel.state.lock().unwrap().waiting.len() != n {
+                sleep(Duration::from_millis(1));
+            }
+        };
+
+        let received = thread::scope(|s| {
+            let consumers: Vec<_> = (0..3)
+                .map(|i| {
+                    // wait for the previous one to be in line.
+                    waiting(i);
+                    let rx = rx.clone();
+                    s.spawn(move || rx.receive().unwrap())
+                })
+                .collect();
+            waiting(3);
+            for i in 0..3 {
+                tx.send(i).unwrap();
+            }
+            consumers
+                .into_iter()
+                .map(|c| c.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        // first come, first served.
+        assert_eq!(received, [0, 1, 2]);
+    }
+
+    #[test]
+    fn dropping_one_receiver_keeps_channel_open() {
+        let (tx, rx) = bounded_channel(1);
+        let rx2 = rx.clone();
+        drop(rx);
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(rx2.receive(), Ok(1));
+    }
 }
@@ -0,0 +1,281 @@
+use std::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr::null_mut,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+};
+
+use crate::{Disconnected, SendError, TryRecvError};
+
+/// Creates an unbounded channel for many senders and one receiver, built
+/// on Dmitry Vyukov's intrusive MPSC queue. Sending never takes a lock, it's
+/// one allocation and a swap. The receiver parks while the queue is empty.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    // the queue always starts with a node whose message was already taken.
+    let stub = Node::new(MaybeUninit::uninit());
+    let a = Arc::new(Channel {
+        head: AtomicPtr::new(stub),
+        tail: UnsafeCell::new(stub),
+        waiter: AtomicPtr::new(null_mut()),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+
+    let receiver = Receiver {
+        channel: a.clone(),
+        _no_sync: PhantomData,
+    };
+    (Sender { channel: a }, receiver)
+}
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    message: MaybeUninit<T>,
+}
+
+impl<T> Node<T> {
+    fn new(message: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            next: AtomicPtr::new(null_mut()),
+            message,
+        }))
+    }
+}
+
+struct Channel<T> {
+    // the newest node, where senders push.
+    head: AtomicPtr<Node<T>>,
+    // the oldest node, its message is already taken. only the receiver uses it.
+    tail: UnsafeCell<*mut Node<T>>,
+    // the receiver's thread while it's waiting. whoever swaps it out owns it.
+    waiter: AtomicPtr<Thread>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+unsafe impl<T> Send for Channel<T> where T: Send {}
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Channel<T> {
+    fn wake_receiver(&self) {
+        // pairs with the fence in Receiver::receive: either we see the
+        // waiter, or the receiver sees what we did before calling this.
+        fence(Ordering::SeqCst);
+        if self.waiter.load(Ordering::Relaxed).is_null() {
+            return;
+        }
+        let waiter = self.waiter.swap(null_mut(), Ordering::Acquire);
+        if !waiter.is_null() {
+            // Safety: we swapped it out, so nobody else has this pointer.
+            unsafe { Box::from_raw(waiter) }.unpark();
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        // Safety: the tail's message was already taken, every node after it
+        // still holds one.
+        unsafe {
+            let mut next = (*tail).next.load(Ordering::Relaxed);
+            drop(Box::from_raw(tail));
+            while !next.is_null() {
+                let mut node = Box::from_raw(next);
+                node.message.assume_init_drop();
+                next = node.next.load(Ordering::Relaxed);
+            }
+        }
+        let waiter = *self.waiter.get_mut();
+        if !waiter.is_null() {
+            drop(unsafe { Box::from_raw(waiter) });
+        }
+    }
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Never blocks. Gives the message back if the receiver is gone.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        if !self.channel.receiver_alive.load(Ordering::Relaxed) {
+            return Err(SendError(message));
+        }
+        let node = Node::new(MaybeUninit::new(message));
+        let prev = self.channel.head.swap(node, Ordering::AcqRel);
+        // until this store the receiver sees prev as the end of the queue.
+        // Safety: the receiver doesn't free prev until its next is set.
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+        self.channel.wake_receiver();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::Release) == 1 {
+            self.channel.wake_receiver();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    // only one thread at a time can be taking from the tail.
+    _no_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> Receiver<T> {
+    fn pop(&self) -> Option<T> {
+        // Safety: Receiver isn't Sync, so we're the only one using the tail.
+        unsafe {
+            let tail = *self.channel.tail.get();
+            // null can also mean a sender is between its swap and its store,
+            // it'll wake us once it's done.
+            let next = (*tail).next.load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            *self.channel.tail.get() = next;
+            drop(Box::from_raw(tail));
+            // next becomes the new tail, so its message counts as taken.
+            Some((*next).message.assume_init_read())
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.channel.senders.load(Ordering::Acquire) == 0
+    }
+
+    /// Blocks until there's a message. Fails once every sender is gone and
+    /// all messages they sent have been received.
+    pub fn receive(&self) -> Result<T, Disconnected> {
+        loop {
+            if let Some(message) = self.pop() {
+                return Ok(message);
+            }
+            if self.is_disconnected() {
+                // the last sender might have sent something right before leaving.
+                return self.pop().ok_or(Disconnected);
+            }
+            let waiter = Box::into_raw(Box::new(thread::current()));
+            let old = self.channel.waiter.swap(waiter, Ordering::AcqRel);
+            if !old.is_null() {
+                // left over from a wait no sender needed to wake us from.
+                drop(unsafe { Box::from_raw(old) });
+            }
+            // pairs with the fence in wake_receiver. look again, in case a
+            // sender sent or left before it could see we're waiting.
+            fence(Ordering::SeqCst);
+            if let Some(message) = self.pop() {
+                return Ok(message);
+            }
+            if !self.is_disconnected() {
+                thread::park();
+            }
+        }
+    }
+
+    /// Returns right away if there's no message.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        match self.pop() {
+            Some(message) => Ok(message),
+            None if self.is_disconnected() => self.pop().ok_or(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.receiver_alive.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::channel;
+    use crate::{Disconnected, SendError, TryRecvError};
+
+    #[test]
+    fn many_senders() {
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            for id in 0..4 {
+                let tx = tx.clone();
+                s.spawn(move || {
+                    for i in 0..250 {
+                        tx.send((id, i)).unwrap();
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut next = [0; 4];
+            while let Ok((id, i)) = rx.receive() {
+                // each sender's messages arrive in the order it sent them.
+                assert_eq!(i, next[id]);
+                next[id] += 1;
+            }
+            assert_eq!(next, [250; 4]);
+        });
+    }
+
+    #[test]
+    fn receiver_parks_until_sent() {
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send("hello").unwrap();
+                thread::sleep(Duration::from_millis(10));
+            });
+            assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+            assert_eq!(rx.receive(), Ok("hello"));
+            // then sleeps until the sender is dropped.
+            assert_eq!(rx.receive(), Err(Disconnected));
+            assert_eq!(rx.try_receive(), Err(TryRecvError::Disconnected));
+        });
+    }
+
+    #[test]
+    fn send_fails_without_receiver() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn drops_unreceived_messages() {
+        let message = Arc::new(());
+        let (tx, rx) = channel();
+        for _ in 0..3 {
+            tx.send(message.clone()).unwrap();
+        }
+        drop(rx.receive());
+        assert_eq!(Arc::strong_count(&message), 3);
+
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&message), 1);
+    }
+}
@@ -1,13 +1,26 @@
 use std::{
     cell::UnsafeCell,
     mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicU8, Ordering},
 };
 
+const EMPTY: u8 = 0;
+// a sender claimed the slot and is writing the message.
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+const TAKEN: u8 = 3;
+
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
-    in_use: AtomicBool,
+    state: AtomicU8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing's been sent yet.
+    Empty,
+    /// The message was already received.
+    AlreadyTaken,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
@@ -16,35 +29,59 @@ impl<T> Channel<T> {
     pub const fn new() -> Self {
         Self {
             message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
-            in_use: AtomicBool::new(false),
+            state: AtomicU8::new(EMPTY),
         }
     }
 
     pub fn send(&self, message: T) {
-        if self.in_use.swap(true, Ordering::Relaxed) {
+        if self.try_send(message).is_err() {
             panic!("can't send more than once!");
         }
+    }
+
+    /// Gives the message back if something was already sent.
+    pub fn try_send(&self, message: T) -> Result<(), T> {
+        if self
+            .state
+            .compare_exchange(EMPTY, WRITING, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(message);
+        }
         unsafe {
             (*self.message.get()).write(message);
         }
-        self.ready.store(true, Ordering::Release);
+        self.state.store(READY, Ordering::Release);
+        Ok(())
     }
 
     pub fn is_ready(&self) -> bool {
-        self.ready.load(Ordering::Relaxed)
+        self.state.load(Ordering::Relaxed) == READY
     }
 
     pub fn receive(&self) -> T {
-        if !self.ready.swap(false, Ordering::Acquire) {
-            panic!("not ready yet.");
+        match self.try_receive() {
+            Ok(message) => message,
+            Err(_) => panic!("not ready yet."),
         }
-        unsafe { (*self.message.get()).assume_init_read() }
+    }
+
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        match self
+            .state
+            .compare_exchange(READY, TAKEN, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => {}
+            Err(TAKEN) => return Err(TryRecvError::AlreadyTaken),
+            Err(_) => return Err(TryRecvError::Empty),
+        }
+        Ok(unsafe { (*self.message.get()).assume_init_read() })
     }
 }
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.state.get_mut() == READY {
             unsafe { self.message.get_mut().assume_init_drop() }
         }
     }
@@ -54,7 +91,7 @@ impl<T> Drop for Channel<T> {
 mod tests {
     use std::thread;
 
-    use super::Channel;
+    use super::{Channel, TryRecvError};
 
     #[test]
     fn test_one_shot() {
@@ -74,4 +111,32 @@ mod tests {
             assert_eq!(channel.receive(), "ello, world");
         });
     }
+
+    #[test]
+    fn try_send_and_try_receive() {
+        let channel = Channel::new();
+        assert_eq!(channel.try_receive(), Err(TryRecvError::Empty));
+
+        assert_eq!(channel.try_send(1), Ok(()));
+        assert_eq!(channel.try_send(2), Err(2));
+
+        assert_eq!(channel.try_receive(), Ok(1));
+        assert_eq!(channel.try_receive(), Err(TryRecvError::AlreadyTaken));
+    }
+
+    #[test]
+    fn racing_receivers() {
+        let channel = Channel::new();
+        channel.send(1);
+
+        let results: Vec<_> = thread::scope(|s| {
+            let a = s.spawn(|| channel.try_receive());
+            let b = s.spawn(|| channel.try_receive());
+            vec![a.join().unwrap(), b.join().unwrap()]
+        });
+
+        // whoever lost must see the message as taken, never as not sent yet.
+        assert!(results.contains(&Ok(1)));
+        assert!(results.contains(&Err(TryRecvError::AlreadyTaken)));
+    }
 }
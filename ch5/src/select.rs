@@ -0,0 +1,288 @@
+use std::{
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::{Channel, Disconnected, Receiver, TryRecvError};
+
+/// Waits on several receivers at once and handles whichever is ready first.
+/// Each arm gets a closure that turns its message into the result of `wait`.
+///
+/// ```ignore
+/// let event = Select::new()
+///     .recv(&commands, Event::Command)
+///     .recv(&shutdown, |_| Event::Shutdown)
+///     .timeout(Duration::from_secs(1), || Event::Idle)
+///     .wait();
+/// ```
+///
+/// A receiver whose senders are all gone counts as ready, its closure gets
+/// `Err(Disconnected)`. When several are ready, the one added first wins.
+pub struct Select<'a, R> {
+    arms: Vec<Arm<'a, R>>,
+    otherwise: Otherwise<'a, R>,
+}
+
+struct Arm<'a, R> {
+    channel: &'a dyn Watch,
+    // runs the arm's closure and returns Some if there was a message.
+    try_fire: Box<dyn FnMut() -> Option<R> + 'a>,
+}
+
+enum Otherwise<'a, R> {
+    Block,
+    Default(Box<dyn FnOnce() -> R + 'a>),
+    Deadline(Instant, Box<dyn FnOnce() -> R + 'a>),
+}
+
+// lets a Select wait on channels of different message types.
+trait Watch {
+    fn watch(&self, thread: &Thread);
+    fn unwatch(&self, thread: &Thread);
+}
+
+impl<T> Watch for Channel<T> {
+    fn watch(&self, thread: &Thread) {
+        self.state.lock().unwrap().selecting.push(thread.clone());
+    }
+
+    fn unwatch(&self, thread: &Thread) {
+        let mut b = self.state.lock().unwrap();
+        if let Some(i) = b.selecting.iter().position(|t| t.id() == thread.id()) {
+            b.selecting.swap_remove(i);
+        }
+    }
+}
+
+impl<'a, R> Select<'a, R> {
+    pub fn new() -> Self {
+        Self {
+            arms: Vec::new(),
+            otherwise: Otherwise::Block,
+        }
+    }
+
+    pub fn recv<T>(
+        mut self,
+        receiver: &'a Receiver<T>,
+        f: impl FnOnce(Result<T, Disconnected>) -> R + 'a,
+    ) -> Self {
+        let mut f = Some(f);
+        let try_fire = move || {
+            let message = match receiver.try_receive() {
+                Ok(message) => Ok(message),
+                Err(TryRecvError::Disconnected) => Err(Disconnected),
+                Err(TryRecvError::Empty) => return None,
+            };
+            // wait returns as soon as one arm fires, so this only runs once.
+            f.take().map(|f| f(message))
+        };
+        self.arms.push(Arm {
+            channel: &*receiver.channel,
+            try_fire: Box::new(try_fire),
+        });
+        self
+    }
+
+    /// The default arm: runs `f` instead of blocking if nothing is ready.
+    /// Replaces an earlier `otherwise` or `timeout`.
+    pub fn otherwise(mut self, f: impl FnOnce() -> R + 'a) -> Self {
+        self.otherwise = Otherwise::Default(Box::new(f));
+        self
+    }
+
+    /// Runs `f` if nothing is ready within `timeout`.
+    /// Replaces an earlier `otherwise` or `timeout`.
+    pub fn timeout(self, timeout: Duration, f: impl FnOnce() -> R + 'a) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.deadline(deadline, f),
+            // a timeout that big is as good as forever.
+            None => Self {
+                otherwise: Otherwise::Block,
+                ..self
+            },
+        }
+    }
+
+    pub fn deadline(mut self, deadline: Instant, f: impl FnOnce() -> R + 'a) -> Self {
+        self.otherwise = Otherwise::Deadline(deadline, Box::new(f));
+        self
+    }
+
+    fn try_arms(&mut self) -> Option<R> {
+        self.arms.iter_mut().find_map(|arm| (arm.try_fire)())
+    }
+
+    /// Blocks until one of the arms is ready, or the default or timeout applies.
+    pub fn wait(mut self) -> R {
+        let thread = thread::current();
+        loop {
+            if let Some(result) = self.try_arms() {
+                return result;
+            }
+            let timeout = match self.otherwise {
+                Otherwise::Block => None,
+                Otherwise::Default(f) => return f(),
+                Otherwise::Deadline(deadline, f) => {
+                    match deadline.checked_duration_since(Instant::now()) {
+                        Some(timeout) if !timeout.is_zero() => {
+                            self.otherwise = Otherwise::Deadline(deadline, f);
+                            Some(timeout)
+                        }
+                        _ => return f(),
+                    }
+                }
+            };
+
+            for arm in &self.arms {
+                arm.channel.watch(&thread);
+            }
+            // look again, in case a message came in before we were watching.
+            let result = self.try_arms();
+            if result.is_none() {
+                match timeout {
+                    Some(timeout) => thread::park_timeout(timeout),
+                    None => thread::park(),
+                }
+            }
+            for arm in &self.arms {
+                arm.channel.unwatch(&thread);
+            }
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+impl<R> Default for Select<'_, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shorthand for a `Select`. Each arm's body becomes a closure, so it can't
+/// `break` or `return` out of the surrounding code, and `default` and
+/// `timeout` are mutually exclusive.
+///
+/// ```ignore
+/// let event = select! {
+///     recv(commands) -> cmd => Event::Command(cmd),
+///     recv(shutdown) -> _ => Event::Shutdown,
+///     timeout(Duration::from_secs(1)) => Event::Idle,
+/// };
+/// ```
+#[macro_export]
+macro_rules! select {
+    (
+        $(recv($rx:expr) -> $message:pat => $body:expr),+
+        $(, default => $default:expr)?
+        $(, timeout($timeout:expr) => $on_timeout:expr)?
+        $(,)?
+    ) => {
+        $crate::select::Select::new()
+            $(.recv(&$rx, |$message| $body))+
+            $(.otherwise(|| $default))?
+            $(.timeout($timeout, || $on_timeout))?
+            .wait()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Select;
+    use crate::{channel, Disconnected};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Command(&'static str),
+        Data(u32),
+        Shutdown,
+        Idle,
+    }
+
+    #[test]
+    fn coordinator() {
+        let (command_tx, commands) = channel();
+        let (data_tx, data) = channel();
+        let (shutdown_tx, shutdown) = channel::<()>();
+
+        let events = thread::scope(|s| {
+            s.spawn(move || {
+                data_tx.send(1).unwrap();
+                thread::sleep(Duration::from_millis(10));
+                command_tx.send("flush").unwrap();
+                thread::sleep(Duration::from_millis(10));
+                drop(shutdown_tx);
+                // keep the other channels open, so shutdown is the only one disconnected.
+                thread::sleep(Duration::from_millis(20));
+            });
+
+            let mut events = Vec::new();
+            loop {
+                let event = Select::new()
+                    .recv(&commands, |c| Event::Command(c.unwrap()))
+                    .recv(&data, |d| Event::Data(d.unwrap()))
+                    .recv(&shutdown, |_| Event::Shutdown)
+                    .wait();
+                if event == Event::Shutdown {
+                    break events;
+                }
+                events.push(event);
+            }
+        });
+
+        assert_eq!(events, [Event::Data(1), Event::Command("flush")]);
+    }
+
+    #[test]
+    fn first_arm_wins() {
+        let (a_tx, a) = channel();
+        let (b_tx, b) = channel();
+        b_tx.send(2).unwrap();
+        a_tx.send(1).unwrap();
+
+        let select = || {
+            crate::select! {
+                recv(a) -> m => m.unwrap(),
+                recv(b) -> m => m.unwrap(),
+                default => 0,
+            }
+        };
+        assert_eq!(select(), 1);
+        assert_eq!(select(), 2);
+        assert_eq!(select(), 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let (_tx, rx) = channel::<u32>();
+
+        let start = Instant::now();
+        let event = crate::select! {
+            recv(rx) -> m => Event::Data(m.unwrap()),
+            timeout(Duration::from_millis(20)) => Event::Idle,
+        };
+        assert_eq!(event, Event::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn disconnected_counts_as_ready() {
+        let (tx, rx) = channel::<u32>();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(tx);
+            });
+            let result = Select::new().recv(&rx, |m| m).wait();
+            assert_eq!(result, Err(Disconnected));
+        });
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Creates a lock-free ring buffer holding up to `capacity` messages, for
+/// exactly one producing and one consuming thread. Neither side ever blocks,
+/// sending to a full buffer or receiving from an empty one just fails.
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be at least 1");
+    let a = Arc::new(RingBuffer {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+
+    (Producer { buffer: a.clone() }, Consumer { buffer: a })
+}
+
+// keeps each index on its own cache line, so the producer writing tail
+// doesn't keep stealing the line the consumer is reading head from.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+struct RingBuffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // the indices only ever go up, a usize won't overflow in practice.
+    // next slot to read, only written by the consumer.
+    head: CachePadded<AtomicUsize>,
+    // next slot to write, only written by the producer.
+    tail: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T> Sync for RingBuffer<T> where T: Send {}
+
+impl<T> RingBuffer<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail - head
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        let head = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        for i in head..tail {
+            unsafe { (*self.slot(i)).assume_init_drop() }
+        }
+    }
+}
+
+pub struct Producer<T> {
+    buffer: Arc<RingBuffer<T>>,
+}
+
+impl<T> Producer<T> {
+    /// Gives the message back if the buffer is full.
+    pub fn try_send(&mut self, message: T) -> Result<(), T> {
+        let tail = self.buffer.tail.load(Ordering::Relaxed);
+        // acquire, so the consumer is done reading the slot we're about to reuse.
+        let head = self.buffer.head.load(Ordering::Acquire);
+        if tail - head == self.buffer.slots.len() {
+            return Err(message);
+        }
+        // Safety: slots from head to tail belong to the consumer, this one doesn't.
+        unsafe { (*self.buffer.slot(tail)).write(message) };
+        self.buffer.tail.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots.len()
+    }
+
+    /// A snapshot, the consumer might be receiving at the same time.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct Consumer<T> {
+    buffer: Arc<RingBuffer<T>>,
+}
+
+impl<T> Consumer<T> {
+    pub fn try_receive(&mut self) -> Option<T> {
+        let head = self.buffer.head.load(Ordering::Relaxed);
+        // acquire, so we see the message the producer wrote before moving tail.
+        let tail = self.buffer.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // Safety: the slot is between head and tail, so it's written and ours.
+        let message = unsafe { (*self.buffer.slot(head)).assume_init_read() };
+        self.buffer.head.store(head + 1, Ordering::Release);
+        Some(message)
+    }
+
+    /// A snapshot, the producer might be sending at the same time.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::ring_buffer;
+
+    #[test]
+    fn full_and_empty() {
+        let (mut tx, mut rx) = ring_buffer(2);
+        assert_eq!(rx.try_receive(), None);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(3));
+        assert_eq!(tx.len(), 2);
+
+        assert_eq!(rx.try_receive(), Some(1));
+        tx.try_send(3).unwrap();
+        assert_eq!(rx.try_receive(), Some(2));
+        assert_eq!(rx.try_receive(), Some(3));
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn in_order_across_threads() {
+        let (mut tx, mut rx) = ring_buffer(16);
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for mut i in 0..10_000 {
+                    while let Err(back) = tx.try_send(i) {
+                        i = back;
+                        thread::yield_now();
+                    }
+                }
+            });
+            for i in 0..10_000 {
+                loop {
+                    if let Some(message) = rx.try_receive() {
+                        assert_eq!(message, i);
+                        break;
+                    }
+                    thread::yield_now();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn drops_unreceived_messages() {
+        let message = Arc::new(());
+        let (mut tx, mut rx) = ring_buffer(4);
+        for _ in 0..3 {
+            tx.try_send(message.clone()).unwrap();
+        }
+        drop(rx.try_receive());
+        assert_eq!(Arc::strong_count(&message), 3);
+
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&message), 1);
+    }
+}
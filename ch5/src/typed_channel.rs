@@ -1,13 +1,27 @@
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::{atomic::{AtomicBool, Ordering},  Arc}};
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::typed_error::{Canceled, TryRecvError};
 
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>){
     let a = Arc::new(Channel {
         message: UnsafeCell::new(MaybeUninit::uninit()),
         ready: AtomicBool::new(false),
+        sender_dropped: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+        receiving_thread: Mutex::new(None),
     });
 
-    (Sender {channel: a.clone()}, Receiver { channel: a})
+    (Sender {channel: a.clone()}, Receiver { channel: a, taken: false })
 }
 
 pub struct Sender<T> {
@@ -17,12 +31,37 @@ pub struct Sender<T> {
 impl<T> Sender<T> {
     pub fn send(self, message: T) {
         unsafe { (*self.channel.message.get()).write(message)};
-        self.channel.ready.store(true, Ordering::Release)
+        self.channel.ready.store(true, Ordering::Release);
+        self.channel.wake_receiver();
+    }
+
+    /// Gives the message back if the receiver is gone.
+    pub fn try_send(self, message: T) -> Result<(), T> {
+        if self.channel.receiver_dropped.load(Ordering::Relaxed) {
+            return Err(message);
+        }
+        self.send(message);
+        Ok(())
+    }
+
+    /// Whether the receiver is gone, so sending is pointless.
+    pub fn is_canceled(&self) -> bool {
+        self.channel.receiver_dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // after send's store to ready, so seeing this means seeing that too.
+        self.channel.sender_dropped.store(true, Ordering::Release);
+        // if we never sent, the receiver would otherwise wait forever.
+        self.channel.wake_receiver();
     }
 }
 
 pub struct  Receiver<T> {
     channel: Arc<Channel<T>>,
+    taken: bool,
 }
 
 impl<T> Receiver<T> {
@@ -36,11 +75,92 @@ impl<T> Receiver<T> {
         }
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
+
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        if self.taken {
+            return Err(TryRecvError::AlreadyTaken);
+        }
+        if !self.channel.ready.swap(false, Ordering::Acquire) {
+            if !self.channel.sender_dropped.load(Ordering::Acquire) {
+                return Err(TryRecvError::Empty);
+            }
+            // it might have sent right before it was dropped.
+            if !self.channel.ready.swap(false, Ordering::Acquire) {
+                return Err(TryRecvError::SenderDropped);
+            }
+        }
+        self.taken = true;
+        Ok(unsafe { (*self.channel.message.get()).assume_init_read() })
+    }
+
+    /// Blocks until the message arrives, or the sender is dropped without
+    /// sending. Panics if the message was already received.
+    pub fn recv(mut self) -> Result<T, Canceled> {
+        match self.wait(None) {
+            Ok(message) => Ok(message),
+            Err(RecvTimeoutError::Canceled) => Err(Canceled),
+            Err(RecvTimeoutError::Timeout) => unreachable!("there's no deadline"),
+        }
+    }
+
+    /// Like `recv`, but gives up after `timeout`. The receiver is kept,
+    /// so you can try again later.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // a timeout too big for an Instant is as good as forever.
+        self.wait(Instant::now().checked_add(timeout))
+    }
+
+    fn wait(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        // the receiver might have been sent to another thread since it last
+        // waited, so record whichever thread is waiting now. the mutex makes
+        // sure the sender either sees this thread or we see its message.
+        *self.channel.receiving_thread.lock().unwrap() = Some(thread::current());
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::AlreadyTaken) => panic!("message already received!"),
+                Err(TryRecvError::SenderDropped) => return Err(RecvTimeoutError::Canceled),
+            }
+            // in case anything else unparks the thread we check again after.
+            match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if !timeout.is_zero() => thread::park_timeout(timeout),
+                    _ => return Err(RecvTimeoutError::Timeout),
+                },
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.receiver_dropped.store(true, Ordering::Relaxed);
+    }
 }
 
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+    // the thread last waiting in recv, if any.
+    receiving_thread: Mutex<Option<Thread>>,
+}
+
+impl<T> Channel<T> {
+    fn wake_receiver(&self) {
+        if let Some(thread) = &*self.receiving_thread.lock().unwrap() {
+            thread.unpark();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Canceled,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
@@ -55,7 +175,10 @@ impl<T> Drop for Channel<T> {
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
     use super::*;
 
     #[test]
@@ -76,4 +199,80 @@ mod tests {
             assert_eq!(receiver.receive(), "hello, world!");
         });
     }
+
+    #[test]
+    fn try_send_and_try_receive() {
+        let (sender, mut receiver) = channel();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(receiver.try_receive(), Ok(1));
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::AlreadyTaken));
+
+        let (sender, mut receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::SenderDropped));
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.try_send(1), Err(1));
+    }
+
+    #[test]
+    fn recv_on_another_thread() {
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            let t = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(10));
+            sender.send("hello, world!");
+            assert_eq!(t.join().unwrap(), Ok("hello, world!"));
+        });
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (sender, mut receiver) = channel();
+        let start = Instant::now();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sender.send(1);
+            });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+        });
+    }
+
+    #[test]
+    fn dropped_sender_wakes_receiver() {
+        let (sender, receiver) = channel::<i32>();
+
+        thread::scope(|s| {
+            let t = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(10));
+            drop(sender);
+            assert_eq!(t.join().unwrap(), Err(Canceled));
+        });
+
+        let (sender, mut receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Canceled)
+        );
+    }
+
+    #[test]
+    fn is_canceled() {
+        let (sender, receiver) = channel::<i32>();
+        assert!(!sender.is_canceled());
+        drop(receiver);
+        assert!(sender.is_canceled());
+    }
 }
@@ -0,0 +1,16 @@
+// shared by the one-shot channels with a separate Sender. unlike the one in
+// one_shot, they can tell when the sender went away.
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing's been sent yet.
+    Empty,
+    /// The message was already received.
+    AlreadyTaken,
+    /// The sender went away without sending.
+    SenderDropped,
+}
+
+/// The sender went away without sending.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Canceled;
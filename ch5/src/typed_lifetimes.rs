@@ -2,6 +2,8 @@ use std::{
     cell::UnsafeCell, marker::PhantomData, mem::MaybeUninit, sync::atomic::{AtomicBool, Ordering}, thread::{self, Thread}
 };
 
+use crate::typed_error::{Canceled, TryRecvError};
+
 pub struct Sender<'a, T> {
     channel: &'a Channel<T>,
     receiving_thread: Thread,
@@ -13,10 +15,34 @@ impl<T> Sender<'_, T> {
         self.channel.ready.store(true, Ordering::Release);
         self.receiving_thread.unpark();
     }
+
+    /// Gives the message back if the receiver is gone.
+    pub fn try_send(self, message: T) -> Result<(), T> {
+        if self.channel.receiver_dropped.load(Ordering::Relaxed) {
+            return Err(message);
+        }
+        self.send(message);
+        Ok(())
+    }
+
+    /// Whether the receiver is gone, so sending is pointless.
+    pub fn is_canceled(&self) -> bool {
+        self.channel.receiver_dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        // after send's store to ready, so seeing this means seeing that too.
+        self.channel.sender_dropped.store(true, Ordering::Release);
+        // if we never sent, the receiver would otherwise park forever.
+        self.receiving_thread.unpark();
+    }
 }
 
 pub struct Receiver<'a, T> {
     channel: &'a Channel<T>,
+    taken: bool,
     _no_send: PhantomData<*const ()>,
 }
 
@@ -25,18 +51,49 @@ impl<T> Receiver<'_, T> {
         self.channel.ready.load(Ordering::Relaxed)
     }
 
-    pub fn receive(self) -> T {
-        // in case anything else unparks the thread we do a while loop.
-        while !self.channel.ready.swap(false, Ordering::Acquire) {
-            thread::park();
+    /// Blocks until the message arrives, or the sender is dropped without
+    /// sending. Panics if the message was already received.
+    pub fn receive(mut self) -> Result<T, Canceled> {
+        // in case anything else unparks the thread we do a loop.
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Empty) => thread::park(),
+                Err(TryRecvError::AlreadyTaken) => panic!("message already received!"),
+                Err(TryRecvError::SenderDropped) => return Err(Canceled),
+            }
+        }
+    }
+
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        if self.taken {
+            return Err(TryRecvError::AlreadyTaken);
+        }
+        if !self.channel.ready.swap(false, Ordering::Acquire) {
+            if !self.channel.sender_dropped.load(Ordering::Acquire) {
+                return Err(TryRecvError::Empty);
+            }
+            // it might have sent right before it was dropped.
+            if !self.channel.ready.swap(false, Ordering::Acquire) {
+                return Err(TryRecvError::SenderDropped);
+            }
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        self.taken = true;
+        Ok(unsafe { (*self.channel.message.get()).assume_init_read() })
+    }
+}
+
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        self.channel.receiver_dropped.store(true, Ordering::Relaxed);
     }
 }
 
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
 }
 
 impl<T> Channel<T> {
@@ -44,12 +101,14 @@ impl<T> Channel<T> {
         Self {
             message: UnsafeCell::new(MaybeUninit::uninit()),
             ready: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
+            receiver_dropped: AtomicBool::new(false),
         }
     }
 
     pub fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
         *self = Self::new();
-        (Sender { channel: self, receiving_thread: thread::current() }, Receiver { channel: self, _no_send: PhantomData })
+        (Sender { channel: self, receiving_thread: thread::current() }, Receiver { channel: self, taken: false, _no_send: PhantomData })
     }
 }
 
@@ -81,7 +140,52 @@ mod tests {
                 t.unpark();
             });
 
-            assert_eq!(receiver.receive(), "hello, world!");
+            assert_eq!(receiver.receive(), Ok("hello, world!"));
         });
     }
+
+    #[test]
+    fn try_send_and_try_receive() {
+        let mut channel = Channel::new();
+        let (sender, mut receiver) = channel.split();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(receiver.try_receive(), Ok(1));
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::AlreadyTaken));
+        drop(receiver);
+
+        let (sender, mut receiver) = channel.split();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::SenderDropped));
+        drop(receiver);
+
+        let (sender, receiver) = channel.split();
+        drop(receiver);
+        assert_eq!(sender.try_send(1), Err(1));
+    }
+
+    #[test]
+    fn dropped_sender_wakes_receiver() {
+        let mut channel = Channel::<i32>::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+
+            s.spawn(move || {
+                thread::sleep(std::time::Duration::from_millis(10));
+                drop(sender);
+            });
+
+            assert_eq!(receiver.receive(), Err(Canceled));
+        });
+    }
+
+    #[test]
+    fn is_canceled() {
+        let mut channel = Channel::<i32>::new();
+        let (sender, receiver) = channel.split();
+        assert!(!sender.is_canceled());
+        drop(receiver);
+        assert!(sender.is_canceled());
+    }
 }
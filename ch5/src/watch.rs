@@ -0,0 +1,183 @@
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
+
+use crate::Disconnected;
+
+/// Creates a channel that only holds the latest value. Sending replaces it
+/// rather than queueing, so a reader that wasn't looking skips straight to
+/// the newest one.
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Channel {
+        value: RwLock::new(initial),
+        state: Mutex::new(State {
+            version: 0,
+            sender_alive: true,
+        }),
+        changed: Condvar::new(),
+    });
+
+    let receiver = Receiver {
+        channel: a.clone(),
+        seen: 0,
+    };
+    (Sender { channel: a }, receiver)
+}
+
+struct Channel<T> {
+    value: RwLock<T>,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    // goes up by one for every value sent.
+    version: u64,
+    sender_alive: bool,
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and wakes every receiver waiting in `changed`.
+    pub fn send(&self, value: T) {
+        let mut v = self.channel.value.write().unwrap();
+        *v = value;
+        // bumped while still holding the write lock, so anyone holding a
+        // read lock sees a version that matches the value.
+        self.channel.state.lock().unwrap().version += 1;
+        drop(v);
+        self.channel.changed.notify_all();
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.channel.value.read().unwrap()
+    }
+
+    /// A new receiver that counts the current value as already seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let seen = self.channel.state.lock().unwrap().version;
+        Receiver {
+            channel: self.channel.clone(),
+            seen,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.state.lock().unwrap().sender_alive = false;
+        self.channel.changed.notify_all();
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    // the last version this receiver has seen.
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// The current value. Doesn't count as seeing it, see `borrow_and_update`.
+    /// Holding on to it keeps the sender from sending.
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.channel.value.read().unwrap()
+    }
+
+    /// Like `borrow`, but `changed` only returns for values newer than this one.
+    pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+        let v = self.channel.value.read().unwrap();
+        self.seen = self.channel.state.lock().unwrap().version;
+        v
+    }
+
+    /// Whether there's a value this receiver hasn't seen yet.
+    pub fn has_changed(&self) -> bool {
+        self.channel.state.lock().unwrap().version != self.seen
+    }
+
+    /// Blocks until there's a value this receiver hasn't seen, and marks it
+    /// as seen. Any number of sends in between count as one change. Fails
+    /// once the sender is gone and there's nothing new left.
+    pub fn changed(&mut self) -> Result<(), Disconnected> {
+        let b = self.channel.state.lock().unwrap();
+        let b = self
+            .channel
+            .changed
+            .wait_while(b, |s| s.version == self.seen && s.sender_alive)
+            .unwrap();
+        if b.version == self.seen {
+            return Err(Disconnected);
+        }
+        self.seen = b.version;
+        Ok(())
+    }
+}
+
+/// The clone has seen the same values as this receiver.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::channel;
+    use crate::Disconnected;
+
+    #[test]
+    fn writes_coalesce() {
+        let (tx, mut rx) = channel(0);
+        assert!(!rx.has_changed());
+
+        for i in 1..=3 {
+            tx.send(i);
+        }
+        assert!(rx.has_changed());
+        assert_eq!(rx.changed(), Ok(()));
+        assert_eq!(*rx.borrow(), 3);
+        assert!(!rx.has_changed());
+    }
+
+    #[test]
+    fn changed_blocks_until_sent() {
+        let (tx, mut rx) = channel("old");
+        let mut rx2 = rx.clone();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send("new");
+            });
+            assert_eq!(rx.changed(), Ok(()));
+            assert_eq!(*rx.borrow(), "new");
+            // then blocks until the sender is dropped.
+            assert_eq!(rx.changed(), Err(Disconnected));
+        });
+
+        // the clone hasn't seen it yet, even though the sender's gone.
+        assert_eq!(rx2.changed(), Ok(()));
+        assert_eq!(*rx2.borrow_and_update(), "new");
+        assert_eq!(rx2.changed(), Err(Disconnected));
+    }
+
+    #[test]
+    fn subscribe_and_borrow_and_update() {
+        let (tx, mut rx) = channel(1);
+        tx.send(2);
+
+        let late = tx.subscribe();
+        assert!(!late.has_changed());
+        assert_eq!(*late.borrow(), 2);
+
+        assert_eq!(*rx.borrow_and_update(), 2);
+        assert!(!rx.has_changed());
+        assert_eq!(*tx.borrow(), 2);
+    }
+}